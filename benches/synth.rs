@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use eframe::egui::Id;
+use tenori_ish::envelope::Envelope;
+use tenori_ish::noise::Note;
+use tenori_ish::synth::{Synth, SAMPLE_RATE};
+use tenori_ish::tenori::LOOP_LENGTH;
+use tenori_ish::timbre::Timbre;
+use tenori_ish::voice::{VoiceAllocator, Voices, DEFAULT_MAX_VOICES};
+
+/// Samples in one step at 120 BPM
+const STEP: usize = SAMPLE_RATE as usize / 2;
+
+/// Every note in one loop of a grid with a four-note chord on every step, using all five
+/// oscillators so both designs have the same amount of synthesis to do
+fn notes() -> Vec<Vec<Note>> {
+    let timbre = Timbre {
+        sine: 0.2,
+        triangle: 0.2,
+        square: 0.2,
+        sawtooth: 0.2,
+        noise: 0.2,
+        envelope: Envelope { attack: 0.05, decay: 0.1, sustain: 0.7, hold: 0.5, release: 0.5 },
+        ..Timbre::default()
+    };
+    (0..LOOP_LENGTH as i32).map(|step| {
+        (0..4).map(|n| Note {
+            tone: step % 5 + n * 3,
+            volume: 0.8,
+            timbre,
+            track: Id::new("bench"),
+            voices: Voices::default()
+        }).collect()
+    }).collect()
+}
+
+/// One rodio source per note, mixed by rodio's mixer
+fn iterator_chains(notes: &[Vec<Note>]) -> f32 {
+    let (mixer, mut output) = rodio::mixer::mixer(1, SAMPLE_RATE);
+    let mut voices = VoiceAllocator::new(DEFAULT_MAX_VOICES);
+    let mut sum = 0.0;
+    for step in notes {
+        for note in step {
+            note.play(&mixer, voices.allocate(note.track, note.voices));
+        }
+        sum += output.by_ref().take(STEP).sum::<f32>();
+    }
+    sum
+}
+
+/// The preallocated voice pool, rendered in blocks
+fn voice_pool(notes: &[Vec<Note>]) -> f32 {
+    let (mut synth, _handle) = Synth::new();
+    let mut sum = 0.0;
+    for step in notes {
+        for note in step {
+            synth.note_on(*note);
+        }
+        sum += synth.by_ref().take(STEP).sum::<f32>();
+    }
+    sum
+}
+
+fn bench_synth(c: &mut Criterion) {
+    let notes = notes();
+    let mut group = c.benchmark_group("one loop of chords");
+    group.sample_size(10);
+    group.bench_function("iterator chains", |b| b.iter(|| iterator_chains(&notes)));
+    group.bench_function("voice pool", |b| b.iter(|| voice_pool(&notes)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_synth);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use eframe::egui::Id;
+use tenori_ish::envelope::Envelope;
+use tenori_ish::noise::Note;
+use tenori_ish::tenori::LOOP_LENGTH;
+use tenori_ish::timbre::Timbre;
+use tenori_ish::voice::{VoiceAllocator, Voices};
+
+/// Samples in one step at 180 BPM
+const STEP: usize = 44100 * 60 / 180;
+
+/// Play one loop of a completely full grid with long notes, and return the most voices
+/// that were ever sounding at once.
+fn full_grid_loop(max_voices: usize) -> usize {
+    let (mixer, mut output) = rodio::mixer::mixer(1, 44100);
+    let mut voices = VoiceAllocator::new(max_voices);
+    let track = Id::new("bench");
+    let timbre = Timbre {
+        envelope: Envelope { attack: 0.0, decay: 0.0, sustain: 0.8, hold: 2.0, release: 1.0 },
+        ..Timbre::default()
+    };
+
+    let mut most = 0;
+    for _ in 0..LOOP_LENGTH {
+        for tone in 0..LOOP_LENGTH as i32 {
+            let note = Note {
+                tone,
+                volume: 1.0,
+                timbre,
+                track,
+                voices: Voices { polyphony: usize::MAX, ..Voices::default() }
+            };
+            note.play(&mixer, voices.allocate(track, note.voices));
+        }
+        output.by_ref().take(STEP).for_each(drop);
+        most = most.max(voices.sounding(None));
+    }
+    most
+}
+
+fn bench_voices(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full grid at 180 BPM");
+    group.sample_size(10);
+    for limit in [16, 32, 64, usize::MAX] {
+        let name = if limit == usize::MAX { "unlimited".to_string() } else { format!("{limit} voices") };
+        group.bench_function(name, |b| b.iter(|| full_grid_loop(limit)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_voices);
+criterion_main!(benches);
@@ -0,0 +1,145 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Settings for a track's arpeggiator, which plays the lit notes in a column one after
+/// another instead of all at once
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Arpeggiator {
+    pub enabled: bool,
+    pub order: ArpOrder,
+    pub rate: ArpRate,
+
+    /// How many octaves to run the notes over (1 means just the notes as written)
+    pub octaves: u32
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            order: ArpOrder::Up,
+            rate: ArpRate::Sixteenth,
+            octaves: 1
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ArpOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    /// The order the notes were turned on in
+    AsPlayed
+}
+
+impl ArpOrder {
+    pub const ALL: [ArpOrder; 5] = [ArpOrder::Up, ArpOrder::Down, ArpOrder::UpDown, ArpOrder::Random, ArpOrder::AsPlayed];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ArpOrder::Up => "Up",
+            ArpOrder::Down => "Down",
+            ArpOrder::UpDown => "Up / down",
+            ArpOrder::Random => "Random",
+            ArpOrder::AsPlayed => "As played"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ArpRate {
+    Eighth,
+    EighthTriplet,
+    Sixteenth,
+    SixteenthTriplet,
+    ThirtySecond
+}
+
+impl ArpRate {
+    pub const ALL: [ArpRate; 5] = [ArpRate::Eighth, ArpRate::EighthTriplet, ArpRate::Sixteenth, ArpRate::SixteenthTriplet, ArpRate::ThirtySecond];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ArpRate::Eighth => "1/8",
+            ArpRate::EighthTriplet => "1/8 triplets",
+            ArpRate::Sixteenth => "1/16",
+            ArpRate::SixteenthTriplet => "1/16 triplets",
+            ArpRate::ThirtySecond => "1/32"
+        }
+    }
+
+    /// How many arpeggio notes fit in one beat, when a beat is a 1/`unit` note
+    pub fn per_beat(self, unit: u32) -> f32 {
+        let per_whole = match self {
+            ArpRate::Eighth => 8.0,
+            ArpRate::EighthTriplet => 12.0,
+            ArpRate::Sixteenth => 16.0,
+            ArpRate::SixteenthTriplet => 24.0,
+            ArpRate::ThirtySecond => 32.0
+        };
+        per_whole / unit.max(1) as f32
+    }
+}
+
+impl Arpeggiator {
+    /// Which tone to play on arpeggio tick number `tick`, out of a column's tones (given in
+    /// the order they were turned on)
+    pub fn tone(&self, tones: &[i32], tick: u32) -> Option<i32> {
+        if tones.is_empty() { return None }
+
+        let mut notes = tones.to_vec();
+        if self.order != ArpOrder::AsPlayed { notes.sort() }
+
+        let octaves = self.octaves.max(1) as i32;
+        let mut pattern: Vec<i32> = (0..octaves)
+            .flat_map(|o| notes.iter().map(move |t| t + o * 12))
+            .collect();
+        if self.order == ArpOrder::Down { pattern.reverse() }
+
+        // Up and back down again, without playing the top and bottom notes twice
+        if self.order == ArpOrder::UpDown && pattern.len() > 2 {
+            let down: Vec<_> = pattern[1..pattern.len() - 1].iter().rev().copied().collect();
+            pattern.extend(down)
+        }
+
+        if self.order == ArpOrder::Random {
+            Some(pattern[rand::rng().random_range(0..pattern.len())])
+        } else {
+            Some(pattern[tick as usize % pattern.len()])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arp(order: ArpOrder, octaves: u32) -> Arpeggiator {
+        Arpeggiator { enabled: true, order, rate: ArpRate::Sixteenth, octaves }
+    }
+
+    fn run(arp: Arpeggiator, tones: &[i32], ticks: u32) -> Vec<i32> {
+        (0..ticks).filter_map(|t| arp.tone(tones, t)).collect()
+    }
+
+    #[test]
+    fn test_orders() {
+        let tones = [4, 0, 7];
+        assert_eq!(run(arp(ArpOrder::Up, 1), &tones, 4), vec![0, 4, 7, 0]);
+        assert_eq!(run(arp(ArpOrder::Down, 1), &tones, 4), vec![7, 4, 0, 7]);
+        assert_eq!(run(arp(ArpOrder::UpDown, 1), &tones, 6), vec![0, 4, 7, 4, 0, 4]);
+        assert_eq!(run(arp(ArpOrder::AsPlayed, 1), &tones, 4), vec![4, 0, 7, 4]);
+        assert!(run(arp(ArpOrder::Random, 1), &tones, 20).iter().all(|t| tones.contains(t)));
+        assert_eq!(run(arp(ArpOrder::Up, 1), &[], 4), vec![]);
+    }
+
+    #[test]
+    fn test_octaves() {
+        assert_eq!(run(arp(ArpOrder::Up, 2), &[0, 4], 5), vec![0, 4, 12, 16, 0]);
+        assert_eq!(run(arp(ArpOrder::Down, 2), &[0, 4], 5), vec![16, 12, 4, 0, 16]);
+        assert_eq!(run(arp(ArpOrder::UpDown, 2), &[0], 3), vec![0, 12, 0]);
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs::{self, File};
+use std::io::{Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+use crate::prefs::data_dir;
+
+/// Where the copy of the song goes, in the data directory, with our process id after it so
+/// every window has its own. It's never the user's own file.
+const RECOVERY_FILE: &str = "recovery-";
+
+/// Left in the data directory while we're running (again with our process id), and taken
+/// away when we exit properly. We hold a lock on it the whole time, so if there's one that
+/// nobody has locked, that session crashed. It holds the file that session was working on.
+const RUNNING_FILE: &str = "running-";
+
+/// Our running file, kept open so we keep the lock
+static RUNNING: Mutex<Option<File>> = Mutex::new(None);
+
+/// Writing a copy of the song every so often, to get back after a crash
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Autosave {
+    pub enabled: bool,
+
+    /// How often, in minutes
+    pub minutes: u32
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self { enabled: true, minutes: 1 }
+    }
+}
+
+impl Autosave {
+    /// Whether it's time for another copy, if we last wrote one at `last`
+    pub fn due(&self, last: Instant, now: Instant) -> bool {
+        self.enabled && now - last >= Duration::from_secs(self.minutes.max(1) as u64 * 60)
+    }
+}
+
+fn recovery_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{RECOVERY_FILE}{id}.tenori"))
+}
+
+fn our_id() -> String {
+    std::process::id().to_string()
+}
+
+/// Write the copy of the song
+pub fn write_recovery(text: &str) -> Result<(), String> {
+    let dir = data_dir().ok_or("Couldn't find a folder to autosave in")?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    fs::write(recovery_path(&dir, &our_id()), text).map_err(|e| e.to_string())
+}
+
+/// Throw away the copy of the song, once there's nothing in it that isn't saved
+pub fn remove_recovery() {
+    let Some(dir) = data_dir() else { return };
+    let _ = fs::remove_file(recovery_path(&dir, &our_id()));
+}
+
+/// Note which file we're working on, so if we crash the copy is only offered back when
+/// it's newer than the file
+pub fn set_session_file(path: Option<&Path>) {
+    let Ok(running) = RUNNING.lock() else { return };
+    let Some(mut file) = running.as_ref() else { return };
+    let path = path.map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let _ = file.set_len(0).and_then(|_| file.rewind()).and_then(|_| file.write_all(path.as_bytes()));
+}
+
+/// Note that a session is starting. Returns the song from a session that crashed, if
+/// there is one with anything in it that isn't in its file.
+pub fn start_session() -> Option<String> {
+    let dir = data_dir()?;
+    fs::create_dir_all(&dir).ok()?;
+    start_session_in(&dir)
+}
+
+fn start_session_in(dir: &Path) -> Option<String> {
+    // Any running file nobody holds the lock on is left over from a crash
+    let mut crashed: Option<(SystemTime, String)> = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let Some(id) = entry.file_name().to_string_lossy().strip_prefix(RUNNING_FILE).map(str::to_string) else { continue };
+        let Ok(running) = File::options().read(true).write(true).open(entry.path()) else { continue };
+        if running.try_lock().is_err() { continue }
+        let file = fs::read_to_string(entry.path()).unwrap_or_default();
+        drop(running);
+
+        let recovery = recovery_path(dir, &id);
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some(copied) = modified(&recovery)
+            && modified(Path::new(&file)).is_none_or(|saved| copied > saved)
+            && crashed.as_ref().is_none_or(|(newest, _)| copied > *newest)
+            && let Ok(text) = fs::read_to_string(&recovery) {
+            crashed = Some((copied, text))
+        }
+        let _ = fs::remove_file(&recovery);
+        let _ = fs::remove_file(entry.path());
+    }
+
+    let running = File::create(dir.join(format!("{RUNNING_FILE}{}", our_id())));
+    if let Ok(running) = running && running.lock().is_ok() && let Ok(mut ours) = RUNNING.lock() {
+        *ours = Some(running)
+    }
+    crashed.map(|(_, text)| text)
+}
+
+/// Note that we're exiting properly, so there's nothing to recover next time
+pub fn end_session() {
+    let Some(dir) = data_dir() else { return };
+    if let Ok(mut running) = RUNNING.lock() {
+        running.take();
+    }
+    let _ = fs::remove_file(recovery_path(&dir, &our_id()));
+    let _ = fs::remove_file(dir.join(format!("{RUNNING_FILE}{}", our_id())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_due() {
+        let start = Instant::now();
+        let autosave = Autosave { enabled: true, minutes: 2 };
+        assert!(!autosave.due(start, start + Duration::from_secs(90)));
+        assert!(autosave.due(start, start + Duration::from_secs(120)));
+        let off = Autosave { enabled: false, ..autosave };
+        assert!(!off.due(start, start + Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn test_crashed() {
+        let dir = std::env::temp_dir().join(format!("tenori-ish-test-{}", our_id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(60);
+
+        // One session crashed with changes since it saved, and another had saved since
+        fs::write(dir.join("song.tenori"), "").unwrap();
+        fs::write(dir.join("running-1"), dir.join("song.tenori").to_string_lossy().as_bytes()).unwrap();
+        fs::write(recovery_path(&dir, "1"), "older").unwrap();
+        File::options().write(true).open(recovery_path(&dir, "1")).unwrap().set_modified(old).unwrap();
+        fs::write(dir.join("running-2"), "").unwrap();
+        fs::write(recovery_path(&dir, "2"), "unsaved").unwrap();
+
+        // And another one is still going
+        let alive = File::create(dir.join("running-3")).unwrap();
+        alive.lock().unwrap();
+        fs::write(recovery_path(&dir, "3"), "someone else's").unwrap();
+
+        assert_eq!(start_session_in(&dir), Some("unsaved".to_string()));
+        assert!(!dir.join("running-1").exists() && !recovery_path(&dir, "1").exists());
+        assert!(dir.join("running-3").exists() && recovery_path(&dir, "3").exists());
+
+        // We're running now, so the next one to start doesn't think we crashed
+        let ours = File::open(dir.join(format!("{RUNNING_FILE}{}", our_id()))).unwrap();
+        assert!(ours.try_lock().is_err());
+        drop(alive);
+        RUNNING.lock().unwrap().take();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
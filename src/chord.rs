@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use crate::scale::Scale;
+
+/// Settings for a track's chord mode, where each lit cell plays a chord built on that row's
+/// note of the scale instead of just the one note
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Chords {
+    pub enabled: bool,
+    pub kind: ChordKind,
+
+    /// For custom chords: scale degrees above the root, separated by spaces (like "0 2 4 6")
+    pub custom: String,
+
+    /// How many of the bottom notes get moved up an octave
+    pub inversion: u32,
+    pub voicing: Voicing
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum ChordKind {
+    #[default]
+    Triad,
+    Seventh,
+    Sus2,
+    Sus4,
+    /// Root, fifth and octave, always a perfect fifth whatever the scale says
+    Power,
+    Custom
+}
+
+impl ChordKind {
+    pub const ALL: [ChordKind; 6] = [ChordKind::Triad, ChordKind::Seventh, ChordKind::Sus2, ChordKind::Sus4, ChordKind::Power, ChordKind::Custom];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChordKind::Triad => "Triad",
+            ChordKind::Seventh => "Seventh",
+            ChordKind::Sus2 => "Sus2",
+            ChordKind::Sus4 => "Sus4",
+            ChordKind::Power => "Power",
+            ChordKind::Custom => "Custom"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum Voicing {
+    /// Every note as close as it can be above the one below it
+    #[default]
+    Close,
+    /// The second note from the bottom moved up an octave
+    Open,
+    /// The second note from the top moved down an octave
+    Drop2
+}
+
+impl Voicing {
+    pub const ALL: [Voicing; 3] = [Voicing::Close, Voicing::Open, Voicing::Drop2];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Voicing::Close => "Close",
+            Voicing::Open => "Open",
+            Voicing::Drop2 => "Drop 2"
+        }
+    }
+}
+
+impl Chords {
+    /// The tones of the chord built on `row` of a scale, lowest first
+    pub fn tones(&self, scale: Scale, row: u32) -> Vec<i32> {
+        let root = row as i32;
+        let mut tones: Vec<i32> = match self.kind {
+            ChordKind::Triad => [0, 2, 4].map(|d| scale.degree(root + d)).to_vec(),
+            ChordKind::Seventh => [0, 2, 4, 6].map(|d| scale.degree(root + d)).to_vec(),
+            ChordKind::Sus2 => [0, 1, 4].map(|d| scale.degree(root + d)).to_vec(),
+            ChordKind::Sus4 => [0, 3, 4].map(|d| scale.degree(root + d)).to_vec(),
+            ChordKind::Power => {
+                let tone = scale.degree(root);
+                vec![tone, tone + 7, tone + 12]
+            }
+            ChordKind::Custom => {
+                let degrees = self.custom.split_whitespace().filter_map(|d| d.parse::<i32>().ok());
+                degrees.map(|d| scale.degree(root + d)).collect()
+            }
+        };
+        tones.sort();
+        tones.dedup();
+        if tones.is_empty() { return vec![scale.degree(root)] }
+
+        for _ in 0..self.inversion as usize % tones.len() {
+            let lowest = tones.remove(0);
+            tones.push(lowest + 12)
+        }
+
+        let n = tones.len();
+        match self.voicing {
+            Voicing::Close => {}
+            Voicing::Open if n > 2 => tones[1] += 12,
+            Voicing::Drop2 if n > 2 => tones[n - 2] -= 12,
+            _ => {}
+        }
+        tones.sort();
+        tones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(kind: ChordKind, inversion: u32, voicing: Voicing) -> Chords {
+        Chords { enabled: true, kind, custom: "0 4 9".to_string(), inversion, voicing }
+    }
+
+    #[test]
+    fn test_kinds() {
+        // C major triad and seventh on the bottom row
+        assert_eq!(chord(ChordKind::Triad, 0, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -5, -2]);
+        assert_eq!(chord(ChordKind::Seventh, 0, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -5, -2, 2]);
+        // D minor, the second degree
+        assert_eq!(chord(ChordKind::Triad, 0, Voicing::Close).tones(Scale::CMajor, 1), vec![-7, -4, 0]);
+        assert_eq!(chord(ChordKind::Sus2, 0, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -7, -2]);
+        assert_eq!(chord(ChordKind::Sus4, 0, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -4, -2]);
+        // A perfect fifth even on the diminished degree
+        assert_eq!(chord(ChordKind::Power, 0, Voicing::Close).tones(Scale::CMajor, 6), vec![2, 9, 14]);
+        assert_eq!(chord(ChordKind::Custom, 0, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -2, 7]);
+        // Past the top of the scale table
+        assert_eq!(chord(ChordKind::Triad, 0, Voicing::Close).tones(Scale::CMajor, 15), vec![17, 20, 24]);
+    }
+
+    #[test]
+    fn test_inversions_and_voicings() {
+        assert_eq!(chord(ChordKind::Triad, 1, Voicing::Close).tones(Scale::CMajor, 0), vec![-5, -2, 3]);
+        assert_eq!(chord(ChordKind::Triad, 2, Voicing::Close).tones(Scale::CMajor, 0), vec![-2, 3, 7]);
+        assert_eq!(chord(ChordKind::Triad, 3, Voicing::Close).tones(Scale::CMajor, 0), vec![-9, -5, -2]);
+        assert_eq!(chord(ChordKind::Triad, 0, Voicing::Open).tones(Scale::CMajor, 0), vec![-9, -2, 7]);
+        assert_eq!(chord(ChordKind::Seventh, 0, Voicing::Drop2).tones(Scale::CMajor, 0), vec![-14, -9, -5, 2]);
+    }
+}
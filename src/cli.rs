@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use crate::render::{render, write_wav};
+use crate::saveload::PersistedTenori;
+use crate::tenori::Tenori;
+
+pub const USAGE: &str = "\
+Usage: tenori-ish [FILE] [--play]
+       tenori-ish FILE --render OUT.wav [--loops N]
+       tenori-ish FILE --validate
+
+Options:
+  --play           Start playing as soon as the window opens
+  --render OUT     Write the song to a WAV file, without a window or sound
+  --loops N        How many times round the loop to render. Without it, it's once round,
+                   or the whole song if the song is on.
+  --validate       Check the file, and say what's wrong with it and on which line
+  --help           Show this";
+
+/// What we were asked to do on the command line
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Args {
+    pub file: Option<PathBuf>,
+    pub play: bool,
+    pub render: Option<PathBuf>,
+    pub loops: Option<u32>,
+    pub validate: bool,
+    pub help: bool
+}
+
+impl Args {
+    /// Read the arguments (leaving off the program name)
+    pub fn parse(args: impl IntoIterator<Item=String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--play" => parsed.play = true,
+                "--validate" => parsed.validate = true,
+                "--help" | "-h" => parsed.help = true,
+                "--render" => {
+                    let out = args.next().ok_or("--render needs a file to write to")?;
+                    parsed.render = Some(out.into())
+                }
+                "--loops" => {
+                    let loops = args.next().ok_or("--loops needs a number")?;
+                    let loops = loops.parse().ok().filter(|n| *n > 0).ok_or(format!("--loops needs a number above 0, not {loops}"))?;
+                    parsed.loops = Some(loops)
+                }
+                option if option.starts_with("--") => return Err(format!("There's no {option} option")),
+                file if parsed.file.is_none() => parsed.file = Some(file.into()),
+                file => return Err(format!("Only one file at a time, but there's {file} as well"))
+            }
+        }
+
+        let needs_file = parsed.render.is_some() || parsed.validate;
+        if needs_file && parsed.file.is_none() && !parsed.help {
+            return Err("--render and --validate need a file to work on".to_string())
+        }
+        if parsed.loops.is_some() && parsed.render.is_none() {
+            return Err("--loops only goes with --render".to_string())
+        }
+        Ok(parsed)
+    }
+
+    /// Whether we're doing something on the command line, rather than opening the window
+    pub fn headless(&self) -> bool {
+        self.help || self.validate || self.render.is_some()
+    }
+
+    /// Do whatever we were asked to on the command line, printing what happened. Returns
+    /// the exit code.
+    pub fn run(&self) -> i32 {
+        if self.help {
+            println!("{USAGE}");
+            return 0
+        }
+        let Some(file) = self.file.as_deref() else {
+            eprintln!("{USAGE}");
+            return 2
+        };
+
+        let result = if self.validate {
+            validate(file)
+        } else if let Some(out) = self.render.as_deref() {
+            render_file(file, out, self.loops)
+        } else {
+            Ok(())
+        };
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}: {e}", file.display());
+                1
+            }
+        }
+    }
+}
+
+/// Loading puts right what it can, but this says what there was to put right, and fails
+/// if there was anything
+fn validate(file: &Path) -> Result<(), String> {
+    let (_, repaired) = PersistedTenori::read(file).map_err(|e| e.to_string())?;
+    if repaired.is_empty() {
+        println!("{}: ok", file.display());
+        return Ok(())
+    }
+    for problem in &repaired {
+        println!("{}: {problem}", file.display())
+    }
+    Err(format!("{} problem{} (loading it would put them right)", repaired.len(), if repaired.len() == 1 { "" } else { "s" }))
+}
+
+fn render_file(file: &Path, out: &Path, loops: Option<u32>) -> Result<(), String> {
+    let (mut tenori, mut synth) = Tenori::headless();
+    let (persisted, repaired) = PersistedTenori::read(file).map_err(|e| e.to_string())?;
+    for problem in &repaired {
+        eprintln!("{}: {problem} (put right)", file.display())
+    }
+    persisted.apply_to(&mut tenori);
+    let samples = render(&mut tenori, &mut synth, loops);
+
+    let wav = File::create(out).map_err(|e| format!("Couldn't write {}: {e}", out.display()))?;
+    write_wav(&mut BufWriter::new(wav), &samples).map_err(|e| format!("Couldn't write {}: {e}", out.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(""), Ok(Args::default()));
+        assert_eq!(parse("song.tenori --play"), Ok(Args { file: Some("song.tenori".into()), play: true, ..Args::default() }));
+        let render = parse("song.tenori --render out.wav --loops 4").unwrap();
+        assert_eq!(render.render, Some("out.wav".into()));
+        assert_eq!(render.loops, Some(4));
+        assert!(render.headless());
+
+        assert!(parse("--render out.wav").is_err());
+        assert!(parse("song.tenori --render").is_err());
+        assert!(parse("song.tenori --render out.wav --loops none").is_err());
+        assert!(parse("song.tenori --loops 2").is_err());
+        assert!(parse("song.tenori --frobnicate").is_err());
+        assert!(parse("one.tenori two.tenori").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+/// A rectangle of cells copied out of a grid, which can be pasted into any grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+    pub width: usize,
+    pub height: usize,
+
+    /// Row by row from the top left, like a grid's cells
+    pub notes: Vec<bool>,
+    pub nudges: Vec<i8>
+}
+
+/// The first line of a clip as text, so we don't try to paste any old text
+const HEADER: &str = "tenori clip";
+
+impl Clip {
+    /// The clip as text for the system clipboard: a row of 0s and 1s for each row of cells,
+    /// then a line for each nudged cell giving its column, row and nudge.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![HEADER.to_string()];
+        for row in self.notes.chunks(self.width.max(1)) {
+            lines.push(row.iter().map(|n| if *n { '1' } else { '0' }).collect())
+        }
+        for (n, nudge) in self.nudges.iter().enumerate().filter(|(_, nudge)| **nudge != 0) {
+            lines.push(format!("{} {} {}", n % self.width, n / self.width, nudge))
+        }
+        lines.join("\n")
+    }
+
+    /// Read a clip back from text, or None if the text isn't one
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut lines = text.lines().map(str::trim);
+        if lines.next()? != HEADER { return None }
+
+        let mut notes = vec![];
+        let mut width = 0;
+        let mut height = 0;
+        let mut nudged = vec![];
+        for line in lines.filter(|l| !l.is_empty()) {
+            if line.chars().all(|c| c == '0' || c == '1') {
+                if height > 0 && line.len() != width { return None }
+                width = line.len();
+                height += 1;
+                notes.extend(line.chars().map(|c| c == '1'))
+            } else {
+                let parts: Vec<_> = line.split_whitespace().collect();
+                let [x, y, nudge] = parts[..] else { return None };
+                nudged.push((x.parse::<usize>().ok()?, y.parse::<usize>().ok()?, nudge.parse::<i8>().ok()?))
+            }
+        }
+        if width == 0 { return None }
+
+        let mut nudges = vec![0; notes.len()];
+        for (x, y, nudge) in nudged.into_iter().filter(|(x, y, _)| *x < width && *y < height) {
+            nudges[x + y * width] = nudge.clamp(-50, 50)
+        }
+        Some(Self { width, height, notes, nudges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let clip = Clip {
+            width: 3,
+            height: 2,
+            notes: vec![true, false, false, false, true, true],
+            nudges: vec![0, 0, 0, 0, -15, 0]
+        };
+        let text = clip.to_text();
+        assert_eq!(text, "tenori clip\n100\n011\n1 1 -15");
+        assert_eq!(Clip::from_text(&text), Some(clip));
+
+        assert_eq!(Clip::from_text("hello"), None);
+        assert_eq!(Clip::from_text("tenori clip\n10\n111"), None);
+        assert_eq!(Clip::from_text("tenori clip\n"), None);
+    }
+}
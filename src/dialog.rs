@@ -1,27 +1,76 @@
-use eframe::egui::{Context, Window};
+use eframe::egui::{Context, Id, Window};
 use crate::gui::Showable;
+use crate::tenori::Tenori;
 
-/// A struct to represent a simple messagebox, like for an error or something.
-/// Contains a string (the message) and a bool which is cleared when the window is
-/// closed.
-pub struct Dialog(pub String, pub bool);
+/// What a dialog's button does, if anything besides closing the dialog
+pub type Action = Box<dyn FnOnce(&mut Tenori)>;
+
+/// A messagebox, like for an error or to check with the user before doing something. It
+/// stays open until one of its buttons is pressed or it's closed.
+pub struct Dialog {
+    pub title: String,
+    pub message: String,
+
+    /// The buttons, left to right, and what each one does
+    pub choices: Vec<(String, Option<Action>)>,
+
+    /// Cleared when the window is closed
+    pub open: bool,
+
+    /// What the button that was pressed does, for the caller to take and do
+    pub chosen: Option<Action>
+}
+
+impl Dialog {
+    /// Ask before doing something we can't take back: one button to go ahead, and one to cancel
+    pub fn confirm(message: impl Into<String>, go_ahead: impl Into<String>, action: impl FnOnce(&mut Tenori) + 'static) -> Self {
+        Self {
+            title: "Are you sure?".to_string(),
+            message: message.into(),
+            choices: vec![(go_ahead.into(), Some(Box::new(action))), ("Cancel".to_string(), None)],
+            open: true,
+            chosen: None
+        }
+    }
+
+    /// Another button, before the others
+    pub fn with_choice(mut self, label: impl Into<String>, action: impl FnOnce(&mut Tenori) + 'static) -> Self {
+        self.choices.insert(0, (label.into(), Some(Box::new(action))));
+        self
+    }
+}
 
 impl<T> Showable<T> for Dialog {
     fn show(&mut self, ctx: &Context, _state: &T) {
-        let win = Window::new("Hey!").resizable(false).scroll([false, false]);
+        let win = Window::new(&self.title)
+            .id(Id::new(("dialog", &self.title, &self.message)))
+            .resizable(false)
+            .collapsible(false)
+            .scroll([false, false]);
         let mut open = true;
         win.open(&mut open).show(ctx, |ui| {
-            ui.label(&self.0);
-            if ui.button("Okay").clicked() {
-                self.1 = false
-            }
+            ui.label(&self.message);
+            ui.horizontal(|ui| {
+                for (label, action) in self.choices.iter_mut() {
+                    if ui.button(label.as_str()).clicked() {
+                        self.chosen = action.take();
+                        self.open = false
+                    }
+                }
+            });
         });
-        if !open { self.1 = false }
+        if !open { self.open = false }
     }
 }
 
 impl<T: AsRef<str>> From<T> for Dialog {
     fn from(value: T) -> Self {
-        Self(value.as_ref().to_string(), true)
+        Self {
+            title: "Hey!".to_string(),
+            message: value.as_ref().to_string(),
+            choices: vec![("Okay".to_string(), None)],
+            open: true,
+            chosen: None
+        }
     }
-}
\ No newline at end of file
+}
@@ -3,6 +3,7 @@ use rodio::{ChannelCount, SampleRate, Source};
 use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, PartialEq, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Envelope {
     pub attack: f32,
     pub decay: f32,
@@ -24,13 +25,57 @@ impl Default for Envelope {
 }
 
 impl Envelope {
+    /// How loud (0.0 .. 1.0) the envelope is `tick` samples in, at `rate` samples per second,
+    /// or None once the release has finished.
+    pub fn gain(&self, mut tick: f32, rate: f32) -> Option<f32> {
+        // Attack phase:
+        if tick < rate * self.attack {
+            let m = 1.0 / self.attack;
+            return Some(tick / rate * m)
+        }
+        tick -= rate * self.attack;
+
+        // Decay phase, reduce to sustain level
+        if tick < rate * self.decay {
+            let m = (1.0 - self.sustain) / self.decay;
+            return Some(1.0 - tick / rate * m)
+        }
+        tick -= rate * self.decay;
+
+        // Hold phase, hold at sustain level:
+        if tick < rate * self.hold {
+            return Some(self.sustain)
+        }
+        tick -= rate * self.hold;
+
+        // Release phase, fade to zero:
+        if tick < rate * self.release {
+            let m = self.sustain / self.release;
+            return Some(self.sustain - tick / rate * m)
+        }
+
+        None
+    }
+
     pub fn modulate<S: Source>(&self, source: S) -> EnvelopeSource<S> {
+        self.modulate_from(source, 0)
+    }
+
+    /// Like `modulate`, but start `elapsed` samples into the envelope. The start point is
+    /// never past the end of the decay phase, so the note always gets its full hold and release.
+    pub fn modulate_from<S: Source>(&self, source: S, elapsed: usize) -> EnvelopeSource<S> {
+        let samples_per_sec = (source.sample_rate() * source.channels() as u32) as f32;
         EnvelopeSource {
             envelope: *self,
             source,
-            elapsed: 0,
+            elapsed: elapsed.min(self.hold_start(samples_per_sec)),
         }
     }
+
+    /// How many samples in the hold phase starts, which is as far in as a note can start
+    pub fn hold_start(&self, samples_per_sec: f32) -> usize {
+        (samples_per_sec * (self.attack + self.decay)) as usize
+    }
 }
 
 pub struct EnvelopeSource<S: Source> {
@@ -47,37 +92,11 @@ impl<S: Source> Iterator for EnvelopeSource<S> {
             let rate = self.source.sample_rate() as f32; // Number of samples per sec
             // Which tick are we on (a given tick might be more than one call,
             // since multiple channels)
-            let mut tick = (self.elapsed / self.source.channels() as usize) as f32;
+            let tick = (self.elapsed / self.source.channels() as usize) as f32;
             // No matter what happens we have now consumed a sample:
             self.elapsed += 1;
 
-            // Attack phase:
-            if tick < rate * self.envelope.attack {
-                let m = 1.0 / self.envelope.attack;
-                return Some(val * tick / rate * m)
-            }
-            tick -= rate * self.envelope.attack;
-
-            // Decay phase, reduce to sustain level
-            if tick < rate * self.envelope.decay {
-                let m = (1.0 - self.envelope.sustain) / self.envelope.decay;
-                return Some(val * (1.0 - tick / rate * m))
-            }
-            tick -= rate * self.envelope.decay;
-
-            // Hold phase, hold at sustain level:
-            if tick < rate * self.envelope.hold {
-                return Some(val * self.envelope.sustain)
-            }
-            tick -= rate * self.envelope.hold;
-
-            // Release phase, fade to zero:
-            if tick < rate * self.envelope.release {
-                let m = self.envelope.sustain / self.envelope.release;
-                return Some(val * (self.envelope.sustain - tick / rate * m))
-            }
-
-            None
+            self.envelope.gain(tick, rate).map(|g| val * g)
         } else {
             None // Inner sample is done so, so are we
         }
@@ -211,6 +230,19 @@ mod tests {
             vec![5.0, 4.5, 4.0, 3.5, 3.0, 2.5, 2.0, 1.5, 1.0, 0.5]);
     }
 
+    #[test]
+    fn test_modulate_from() {
+        // Start partway through the attack
+        assert_close_enough(
+            env(1.0, 0.0, 1.0, 1.0, 0.0).modulate_from(ConstSource(10.0), 5),
+            vec![5.0, 6.0, 7.0, 8.0, 9.0, 10.0]);
+
+        // Starting past the decay phase clamps to the start of the hold
+        assert_eq!(
+            env(0.2, 0.3, 0.5, 1.0, 0.0).modulate_from(ConstSource(10.0), 100).collect::<Vec<_>>(),
+            vec![5.0; 10]);
+    }
+
     #[test]
     fn test_adsr() {
         // The entire envelope
@@ -1,38 +1,228 @@
 use color::ColorSpace;
 use std::ops::RangeInclusive;
 use eframe::egui;
-use eframe::egui::{Color32, Context, Id, PointerButton, Pos2, Rangef, Sense, Ui, Vec2};
+use eframe::egui::{Color32, Context, Id, Pos2, Rangef, Rect, Sense, StrokeKind, Ui, Vec2};
 use rand::Rng;
+use crate::arp::{ArpOrder, ArpRate, Arpeggiator};
+use crate::chord::{ChordKind, Chords, Voicing};
+use crate::clip::Clip;
 use crate::gui::Showable;
+use crate::noise::Note;
 use crate::scale::Scale;
-use crate::tenori::LOOP_LENGTH;
+use crate::song::pattern_name;
+use crate::tenori::{swing_offset, LOOP_LENGTH};
 use crate::timbre::Timbre;
+use crate::transform::Transform;
+use crate::voice::{VoiceMode, Voices};
+
+/// How many patterns (A, B, C...) each grid has
+pub const PATTERNS: usize = 8;
+
+/// What dragging across the grid does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tool {
+    /// Paint cells on or off
+    Draw,
+    /// Pick out a rectangle of cells, to copy or move
+    Select
+}
+
+/// A rectangle of cells, by column and row counting from the top left (both ends included)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Selection {
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize
+}
+
+impl Selection {
+    /// The rectangle with two cells (in either order) at its corners
+    pub fn new((x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Self {
+        Self { left: x0.min(x1), top: y0.min(y1), right: x0.max(x1), bottom: y0.max(y1) }
+    }
+
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x >= self.left && x <= self.right && y >= self.top && y <= self.bottom
+    }
+
+    pub fn width(&self) -> usize {
+        self.right - self.left + 1
+    }
+
+    pub fn height(&self) -> usize {
+        self.bottom - self.top + 1
+    }
+
+    /// The indices of the cells inside, row by row
+    pub fn cells(&self) -> impl Iterator<Item=usize> + use<> {
+        let Self { left, top, right, bottom } = *self;
+        (top..=bottom).flat_map(move |y| (left..=right).map(move |x| x + y * LOOP_LENGTH as usize))
+    }
+}
+
+/// A drag across the grid that we're in the middle of
+#[derive(Clone)]
+pub enum Drag {
+    /// Painting cells on (or off), from the cell it started at to the one it's got to.
+    /// `before` is the notes (and the order they were played in) from before the drag, so a
+    /// straight line can be redrawn as it moves.
+    Paint { on: bool, start: usize, last: usize, before: (Pattern, Vec<usize>) },
+
+    /// Drawing a selection rectangle out from a corner
+    Marquee { anchor: (usize, usize) },
+
+    /// Moving the selected cells, which were picked up from `from` at cell `grab`
+    Move { grab: (usize, usize), from: Selection, clip: Clip, before: (Pattern, Vec<usize>) }
+}
+
+/// Something to do with the selection that needs the clipboard, for the caller to do
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Copy,
+    Cut,
+    /// Paste some text from the system clipboard, or whatever we copied last
+    Paste(Option<String>),
+    Delete
+}
+
+/// The notes of one of a grid's patterns
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    pub notes: Vec<bool>,
+    pub nudges: Vec<i8>
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self {
+            notes: vec![false; (LOOP_LENGTH * LOOP_LENGTH) as usize],
+            nudges: vec![0; (LOOP_LENGTH * LOOP_LENGTH) as usize]
+        }
+    }
+}
+
+/// A track's settings, apart from its notes, for undo to put back
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackSettings {
+    pub name: String,
+    pub volume: f32,
+    pub muted: bool,
+    pub scale: Scale,
+    pub swing: Option<f32>,
+    pub timbre: Timbre,
+    pub color: Color32,
+    pub voices: Voices,
+    pub arp: Arpeggiator,
+    pub chords: Chords
+}
+
+/// What a grid needs to know about the rest of the program to draw itself
+pub struct GridState {
+    /// What fraction we are through the loop, for drawing the cursor
+    pub cursor: f32,
+    pub playing: bool,
+
+    /// Whether this is the grid the keyboard plays, and whether it's recording
+    pub selected: bool,
+    pub recording: bool,
+
+    /// Whether turning a cell on should play it
+    pub audition: bool
+}
 
 #[derive(Clone)]
 pub struct Grid {
     pub volume: f32,
+    pub muted: bool,
     pub scale: Scale,
+
+    /// The notes of the current pattern
     pub notes: Vec<bool>,
+
+    /// How early or late each cell plays, in hundredths of a step (-50 .. 50)
+    pub nudges: Vec<i8>,
+
+    /// Which pattern is in `notes` and `nudges` right now
+    pub pattern: usize,
+
+    /// The pattern we'll switch to at the end of this loop
+    pub queued: Option<usize>,
+
+    /// All the other patterns. The slot for the current one is left blank, since its
+    /// notes are in `notes` and `nudges`; use `get_pattern` and `set_patterns` rather
+    /// than touching this directly.
+    pub patterns: Vec<Pattern>,
+
+    /// This track's own swing, if it doesn't use the global one
+    pub swing: Option<f32>,
     pub id: Id,
     pub name: String,
     pub open: bool,
     pub timbre: Timbre,
     pub timbre_open: bool,
-    pub color: Color32
+    pub color: Color32,
+    pub voices: Voices,
+    pub arp: Arpeggiator,
+    pub chords: Chords,
+
+    /// Indices of lit cells, in the order they were turned on, for the arpeggiator's
+    /// "as played" order. This isn't saved, and cells missing from it just go last.
+    pub played: Vec<usize>,
+
+    /// Set when the window is clicked on, so the caller can make this the selected grid
+    pub clicked: bool,
+
+    /// Tones to play right away, for the caller to take and play
+    pub audition: Vec<i32>,
+
+    pub tool: Tool,
+    pub selection: Option<Selection>,
+
+    /// The drag we're in the middle of, if any
+    pub drag: Option<Drag>,
+
+    /// Set by the edit menu, for the caller to take and do
+    pub edit: Option<Edit>,
+
+    /// Set by the Clear button, for the caller to check with the user and then clear
+    pub clearing: bool,
+
+    /// How much of the pattern (0.0 .. 1.0) the transform menu thins out
+    pub thin: f32
 }
 
 impl Grid {
     pub fn new(id: Id) -> Self {
         let color = Self::random_color();
+        let Pattern { notes, nudges } = Pattern::default();
 
         Self {
             volume: 1.0,
+            muted: false,
             open: true,
             scale: Scale::CMajor,
-            notes: vec![false; (LOOP_LENGTH * LOOP_LENGTH) as usize],
+            notes,
+            nudges,
+            pattern: 0,
+            queued: None,
+            patterns: vec![Pattern::default(); PATTERNS],
+            swing: None,
             name: "New Track".to_string(),
             timbre: Timbre::default(),
             timbre_open: false,
+            voices: Voices::default(),
+            arp: Arpeggiator::default(),
+            chords: Chords::default(),
+            played: vec![],
+            clicked: false,
+            audition: vec![],
+            tool: Tool::Draw,
+            selection: None,
+            drag: None,
+            edit: None,
+            clearing: false,
+            thin: 0.25,
             color,
             id
         }
@@ -47,7 +237,28 @@ impl Grid {
             (rgb[2] * 255.0) as u8)
     }
 
-    fn draw_grid(&mut self, ui: &mut Ui, cursor: f32) {
+    /// A strip of piano keys down the side, one per row, which play the row when clicked
+    fn draw_keys(&mut self, ui: &mut Ui) {
+        let dim = 20.0 * LOOP_LENGTH as f32;
+        let (rect, response) = ui.allocate_exact_size(Vec2::new(14.0, dim), Sense::click());
+
+        for y in 0..LOOP_LENGTH {
+            // Black keys are the ones that aren't in C major, counting from C
+            let tone = self.scale.tone(LOOP_LENGTH - y - 1);
+            let black = [1, 3, 6, 8, 10].contains(&(tone + 9).rem_euclid(12));
+            let key = egui::Rect::from_min_size(
+                Pos2::new(rect.left(), rect.top() + (y * 20) as f32 + 1.0),
+                Vec2::new(rect.width(), 18.0));
+            ui.painter().rect_filled(key, 2.0, if black { Color32::from_gray(0x22) } else { Color32::from_gray(0xdd) });
+        }
+
+        if response.clicked() && let Some(pos) = response.interact_pointer_pos() {
+            let y = ((pos.y - rect.top()) / 20.0).floor().clamp(0.0, (LOOP_LENGTH - 1) as f32) as u32;
+            self.audition.extend(self.row_tones(LOOP_LENGTH - y - 1))
+        }
+    }
+
+    fn draw_grid(&mut self, ui: &mut Ui, cursor: f32, audition: bool) {
         let dim = 20.0 * LOOP_LENGTH as f32;
         let (rect, response) = ui.allocate_exact_size(Vec2::new(dim, dim), Sense::click_and_drag());
 
@@ -57,57 +268,424 @@ impl Grid {
                 (x * 20 + 10) as f32 + rect.left(),
                 (y * 20 + 10) as f32 + rect.top());
             if *lit {
+                // Nudged notes are drawn off-center, in the direction they've been moved
+                let center = center + Vec2::new(self.nudges[i] as f32 / 100.0 * 20.0, 0.0);
                 ui.painter().circle_filled(center, 10.0, self.color);
             } else {
                 ui.painter().circle_stroke(center, 10.0, (1.0, Color32::from_gray(0x88)));
             }
         }
 
+        if let Some(sel) = self.selection && self.tool == Tool::Select {
+            let min = rect.left_top() + Vec2::new(sel.left as f32, sel.top as f32) * 20.0;
+            let size = Vec2::new(sel.width() as f32, sel.height() as f32) * 20.0;
+            ui.painter().rect_stroke(Rect::from_min_size(min, size), 2.0, (1.5, Color32::WHITE), StrokeKind::Inside);
+        }
+
         ui.painter().vline(
             cursor * dim + rect.left(),
             Rangef::new(rect.top(), rect.top() + dim),
             (1.0, self.color)
         );
 
-        if response.contains_pointer() {
-            ui.input(|input| {
-                if input.pointer.button_clicked(PointerButton::Primary) &&
-                    let Some(pos) = input.pointer.latest_pos() {
-                        let x = ((pos.x - rect.left()) / 20.0).floor() as usize;
-                        let y = ((pos.y - rect.top()) / 20.0).floor() as usize;
-                        let n = x + y * LOOP_LENGTH as usize;
-                        self.notes[n] = !self.notes[n]
-                    }
-            })
+        let (pos, primary, secondary, down, shift, scroll) = ui.input(|i| (
+            i.pointer.latest_pos(),
+            i.pointer.primary_pressed(),
+            i.pointer.secondary_pressed(),
+            i.pointer.any_down(),
+            i.modifiers.shift,
+            i.raw_scroll_delta.y
+        ));
+        let Some(pos) = pos else { return };
+
+        // The cell under the pointer, or the nearest one if it's been dragged off the grid
+        let last = (LOOP_LENGTH - 1) as f32;
+        let x = ((pos.x - rect.left()) / 20.0).floor().clamp(0.0, last) as usize;
+        let y = ((pos.y - rect.top()) / 20.0).floor().clamp(0.0, last) as usize;
+        let n = x + y * LOOP_LENGTH as usize;
+
+        if response.contains_pointer() && (primary || secondary) {
+            self.start_drag((x, y), primary, audition)
+        } else if let Some(drag) = self.drag.take() && down {
+            self.continue_drag(drag, (x, y), shift, audition)
+        }
+
+        // Scrolling over a lit cell nudges it early or late
+        if response.contains_pointer() && scroll != 0.0 && self.notes[n] {
+            let step = if scroll > 0.0 { 5 } else { -5 };
+            self.nudges[n] = (self.nudges[n] + step).clamp(-50, 50)
+        }
+    }
+
+    /// Start dragging from a cell, with the left button (or the right one)
+    fn start_drag(&mut self, (x, y): (usize, usize), primary: bool, audition: bool) {
+        let n = x + y * LOOP_LENGTH as usize;
+        match self.tool {
+            Tool::Select => match self.selection {
+                Some(from) if primary && from.contains((x, y)) => {
+                    let clip = self.copy(from);
+                    self.drag = Some(Drag::Move { grab: (x, y), from, clip, before: self.snapshot() })
+                }
+                _ => {
+                    self.selection = Some(Selection::new((x, y), (x, y)));
+                    self.drag = Some(Drag::Marquee { anchor: (x, y) })
+                }
+            }
+            Tool::Draw => {
+                // The first cell decides whether a stroke turns cells on or off, and
+                // right-dragging always erases
+                let on = primary && !self.notes[n];
+                self.drag = Some(Drag::Paint { on, start: n, last: n, before: self.snapshot() });
+                self.paint(&[n], on, audition)
+            }
+        }
+    }
+
+    /// Carry on a drag to the cell that the pointer is now over
+    fn continue_drag(&mut self, mut drag: Drag, (x, y): (usize, usize), shift: bool, audition: bool) {
+        let n = x + y * LOOP_LENGTH as usize;
+        match &mut drag {
+            Drag::Paint { on, start, last, before } if n != *last => {
+                if shift {
+                    // A straight line from where the stroke started, instead of whatever
+                    // we'd drawn before
+                    self.restore(before.clone());
+                    self.paint(&line(*start, n), *on, false)
+                } else {
+                    // Fill in the gap, in case the pointer moved more than a cell since last time
+                    self.paint(&line(*last, n)[1..], *on, audition)
+                }
+                *last = n;
+            }
+            Drag::Paint { .. } => {}
+            Drag::Marquee { anchor } => self.selection = Some(Selection::new(*anchor, (x, y))),
+            Drag::Move { grab, from, clip, before } => {
+                // Keep the whole selection on the grid
+                let edge = LOOP_LENGTH as i32 - 1;
+                let dx = (x as i32 - grab.0 as i32).clamp(-(from.left as i32), edge - from.right as i32);
+                let dy = (y as i32 - grab.1 as i32).clamp(-(from.top as i32), edge - from.bottom as i32);
+                let (left, top) = ((from.left as i32 + dx) as usize, (from.top as i32 + dy) as usize);
+
+                self.restore(before.clone());
+                self.clear_cells(*from);
+                self.stamp(clip, (left, top));
+            }
+        }
+        self.drag = Some(drag)
+    }
+
+    /// The current notes, and the order they were played in, to put back later
+    fn snapshot(&self) -> (Pattern, Vec<usize>) {
+        (self.get_pattern(self.pattern), self.played.clone())
+    }
+
+    fn restore(&mut self, (pattern, played): (Pattern, Vec<usize>)) {
+        self.notes = pattern.notes;
+        self.nudges = pattern.nudges;
+        self.played = played;
+    }
+
+    /// The cells in a rectangle
+    pub fn copy(&self, sel: Selection) -> Clip {
+        Clip {
+            width: sel.width(),
+            height: sel.height(),
+            notes: sel.cells().map(|n| self.notes[n]).collect(),
+            nudges: sel.cells().map(|n| self.nudges[n]).collect()
+        }
+    }
+
+    /// Turn off every cell in a rectangle
+    fn clear_cells(&mut self, sel: Selection) {
+        for n in sel.cells() {
+            if self.notes[n] { self.toggle(n) }
+        }
+    }
+
+    /// Put a clip's cells in with its top left corner at a cell, cutting off anything that
+    /// goes off the grid, and select them
+    fn stamp(&mut self, clip: &Clip, (left, top): (usize, usize)) {
+        let edge = LOOP_LENGTH as usize - 1;
+        let sel = Selection::new((left, top), ((left + clip.width - 1).min(edge), (top + clip.height - 1).min(edge)));
+        for (i, n) in sel.cells().enumerate() {
+            let c = i % sel.width() + i / sel.width() * clip.width;
+            if self.notes[n] != clip.notes[c] { self.toggle(n) }
+            self.nudges[n] = clip.nudges[c];
         }
+        self.selection = Some(sel)
     }
 
-    pub fn notes(&self, beat: u32) -> Vec<i32> {
+    /// Rearrange the selected cells, or the whole grid if nothing's selected
+    pub fn transform(&mut self, transform: Transform) {
+        let selection = self.selection;
+        let edge = LOOP_LENGTH as usize - 1;
+        let region = selection.unwrap_or(Selection::new((0, 0), (edge, edge)));
+        let clip = self.copy(region).transform(transform);
+        self.stamp(&clip, (region.left, region.top));
+        self.selection = selection
+    }
+
+    /// The selected cells, if there are any
+    pub fn copy_selection(&self) -> Option<Clip> {
+        self.selection.map(|sel| self.copy(sel))
+    }
+
+    /// Turn off every selected cell
+    pub fn delete_selection(&mut self) {
+        if let Some(sel) = self.selection { self.clear_cells(sel) }
+    }
+
+    /// Paste at the top left of the selection (or of the grid), and select what was pasted
+    pub fn paste(&mut self, clip: &Clip) {
+        if clip.width == 0 || clip.height == 0 { return }
+        let (left, top) = self.selection.map(|s| (s.left, s.top)).unwrap_or((0, 0));
+        self.stamp(clip, (left, top));
+        self.tool = Tool::Select
+    }
+
+    /// Turn cells on or off as part of a stroke, maybe playing the ones that come on
+    fn paint(&mut self, cells: &[usize], on: bool, audition: bool) {
+        for &n in cells {
+            if self.notes[n] == on { continue }
+            self.toggle(n);
+            if audition && on {
+                self.audition.extend(self.row_tones(Grid::row(n)))
+            }
+        }
+    }
+
+    /// Set a cell (lit or not, and its nudge) in any pattern, current or not
+    pub fn set_cell(&mut self, pattern: usize, n: usize, (on, nudge): (bool, i8)) {
+        if pattern == self.pattern {
+            if self.notes[n] != on { self.toggle(n) }
+            self.nudges[n] = nudge
+        } else if let Some(pattern) = self.patterns.get_mut(pattern) {
+            pattern.notes[n] = on;
+            pattern.nudges[n] = nudge
+        }
+    }
+
+    pub fn settings(&self) -> TrackSettings {
+        TrackSettings {
+            name: self.name.clone(),
+            volume: self.volume,
+            muted: self.muted,
+            scale: self.scale,
+            swing: self.swing,
+            timbre: self.timbre,
+            color: self.color,
+            voices: self.voices,
+            arp: self.arp,
+            chords: self.chords.clone()
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &TrackSettings) {
+        self.name = settings.name.clone();
+        self.volume = settings.volume;
+        self.muted = settings.muted;
+        self.scale = settings.scale;
+        self.swing = settings.swing;
+        self.timbre = settings.timbre;
+        self.color = settings.color;
+        self.voices = settings.voices;
+        self.arp = settings.arp;
+        self.chords = settings.chords.clone();
+    }
+
+    /// Turn a cell on or off
+    pub fn toggle(&mut self, n: usize) {
+        self.notes[n] = !self.notes[n];
+        self.nudges[n] = 0;
+        self.played.retain(|p| *p != n);
+        if self.notes[n] { self.played.push(n) }
+    }
+
+    /// Switch to another pattern, putting away the current one
+    pub fn select_pattern(&mut self, pattern: usize) {
+        if pattern == self.pattern || pattern >= PATTERNS { return }
+        self.patterns[self.pattern] = Pattern {
+            notes: std::mem::take(&mut self.notes),
+            nudges: std::mem::take(&mut self.nudges)
+        };
+        let Pattern { notes, nudges } = std::mem::take(&mut self.patterns[pattern]);
+        self.notes = notes;
+        self.nudges = nudges;
+        self.pattern = pattern;
+        self.queued = None;
+        self.played.clear();
+    }
+
+    /// A copy of one of the patterns, whether or not it's the current one
+    pub fn get_pattern(&self, pattern: usize) -> Pattern {
+        if pattern == self.pattern {
+            Pattern { notes: self.notes.clone(), nudges: self.nudges.clone() }
+        } else {
+            self.patterns[pattern].clone()
+        }
+    }
+
+    /// Bring in a pattern from elsewhere (like a scene) and select it, without losing any of
+    /// the patterns already here: a pattern that's the same is used if there is one, then an
+    /// empty one. Only if they're all in use does it go over the current pattern.
+    pub fn load_pattern(&mut self, pattern: &Pattern) {
+        let slots = || std::iter::once(self.pattern).chain(0..PATTERNS);
+        let slot = slots().find(|p| self.get_pattern(*p) == *pattern)
+            .or_else(|| slots().find(|p| self.get_pattern(*p) == Pattern::default()))
+            .unwrap_or(self.pattern);
+        self.select_pattern(slot);
+        self.notes = pattern.notes.clone();
+        self.nudges = pattern.nudges.clone();
+        self.played.clear();
+    }
+
+    /// Replace all the patterns at once, and select one of them
+    pub fn set_patterns(&mut self, mut patterns: Vec<Pattern>, selected: usize) {
+        patterns.resize(PATTERNS, Pattern::default());
+        let selected = selected.min(PATTERNS - 1);
+        let Pattern { notes, nudges } = std::mem::take(&mut patterns[selected]);
+        self.notes = notes;
+        self.nudges = nudges;
+        self.patterns = patterns;
+        self.pattern = selected;
+        self.played.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.notes = vec![false; (LOOP_LENGTH * LOOP_LENGTH) as usize];
+        self.nudges = vec![0; (LOOP_LENGTH * LOOP_LENGTH) as usize];
+        self.played.clear();
+    }
+
+    /// Turn off every cell in a column
+    pub fn clear_column(&mut self, column: u32) {
+        for y in 0..LOOP_LENGTH {
+            let n = (y * LOOP_LENGTH + column) as usize;
+            if self.notes[n] { self.toggle(n) }
+        }
+    }
+
+    /// Whether any of the patterns have any notes in them
+    pub fn has_notes(&self) -> bool {
+        self.notes.contains(&true) || self.patterns.iter().any(|p| p.notes.contains(&true))
+    }
+
+    /// Which row (counting up from the bottom) cell `n` is in
+    pub fn row(n: usize) -> u32 {
+        LOOP_LENGTH - n as u32 / LOOP_LENGTH - 1
+    }
+
+    /// When cell `n` plays, in steps, after swing and its nudge. This is in
+    /// (0.0 ..= LOOP_LENGTH), so the first step of the loop is at the very end.
+    pub fn cell_time(&self, n: usize, swing: f32) -> f32 {
+        let step = n as u32 % LOOP_LENGTH;
+        let t = step as f32 + swing_offset(step, swing) + self.nudges[n] as f32 / 100.0;
+        if t <= 0.0 { t + LOOP_LENGTH as f32 } else { t }
+    }
+
+    /// Like `notes`, but in the order the cells were turned on
+    pub fn notes_as_played(&self, step: u32) -> Vec<i32> {
+        let column = |n: usize| n as u32 % LOOP_LENGTH == step && self.notes[n];
+        let mut cells: Vec<usize> = self.played.iter().copied().filter(|n| column(*n)).collect();
+        let unplayed: Vec<usize> = (0..LOOP_LENGTH)
+            .map(|y| (y * LOOP_LENGTH + step) as usize)
+            .filter(|n| column(*n) && !cells.contains(n))
+            .collect();
+        cells.extend(unplayed);
+
+        let mut notes = vec![];
+        for n in cells {
+            for tone in self.row_tones(Self::row(n)) {
+                if !notes.contains(&tone) { notes.push(tone) }
+            }
+        }
+        notes
+    }
+
+    pub fn notes(&self, step: u32) -> Vec<i32> {
         let mut notes = vec![];
         for y in 0..LOOP_LENGTH {
-            if self.notes[(y * LOOP_LENGTH + beat) as usize] {
+            if self.notes[(y * LOOP_LENGTH + step) as usize] {
                 let row = LOOP_LENGTH - y - 1;
-                notes.push(self.scale.tone(row))
+                notes.extend(self.row_tones(row))
             }
         }
+        notes.sort();
+        notes.dedup();
         notes
     }
+
+    /// A note from this grid, with its timbre and volume
+    pub fn note(&self, tone: i32) -> Note {
+        Note {
+            tone,
+            volume: self.volume,
+            timbre: self.timbre,
+            track: self.id,
+            voices: self.voices
+        }
+    }
+
+    /// The tones a lit cell in a row plays: just the row's note, or a chord in chord mode
+    pub fn row_tones(&self, row: u32) -> Vec<i32> {
+        if self.chords.enabled {
+            self.chords.tones(self.scale, row)
+        } else {
+            vec![self.scale.tone(row)]
+        }
+    }
 }
 
-impl Showable<f32> for Grid {
-    fn show(&mut self, ctx: &Context, cursor: &f32) {
+impl Showable<GridState> for Grid {
+    fn show(&mut self, ctx: &Context, state: &GridState) {
         let mut open = true;
         let win = egui::Window::new(&self.name)
             .id(self.id)
             .resizable(false)
             .scroll([false, false])
             .open(&mut open);
-        win.show(ctx, |ui| {
+        let shown = win.show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
                 if ui.button("Clear").clicked() {
-                    self.notes = vec![false; (LOOP_LENGTH * LOOP_LENGTH) as usize]
+                    self.clearing = true
                 }
 
+                ui.selectable_value(&mut self.tool, Tool::Draw, "Draw")
+                    .on_hover_text("Drag to paint, right-drag to erase, shift-drag for a straight line");
+                ui.selectable_value(&mut self.tool, Tool::Select, "Select")
+                    .on_hover_text("Drag to select cells, and drag the selection to move it");
+                if self.tool == Tool::Draw { self.selection = None }
+
+                ui.menu_button("Transform...", |ui| {
+                    ui.label(if self.selection.is_some() { "The selection" } else { "The whole grid" });
+                    let transforms = [
+                        (Transform::RotateLeft, "Shift left"),
+                        (Transform::RotateRight, "Shift right"),
+                        (Transform::Up, "Up a degree"),
+                        (Transform::Down, "Down a degree"),
+                        (Transform::Reverse, "Reverse"),
+                        (Transform::Flip, "Flip upside down"),
+                        (Transform::Invert, "Invert"),
+                        (Transform::Double, "Double speed"),
+                        (Transform::Halve, "Half speed")
+                    ];
+                    for (transform, label) in transforms {
+                        if ui.button(label).clicked() { self.transform(transform) }
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Thin out").clicked() { self.transform(Transform::Thin(self.thin)) }
+                        ui.add(egui::Slider::new(&mut self.thin, RangeInclusive::new(0.0, 1.0)).custom_formatter(|n, _| format!("{:.0}%", n * 100.0)));
+                    });
+                });
+
+                ui.menu_button("Edit...", |ui| {
+                    let selected = self.selection.is_some();
+                    if ui.add_enabled(selected, egui::Button::new("Copy")).clicked() { self.edit = Some(Edit::Copy) }
+                    if ui.add_enabled(selected, egui::Button::new("Cut")).clicked() { self.edit = Some(Edit::Cut) }
+                    if ui.button("Paste").clicked() { self.edit = Some(Edit::Paste(None)) }
+                    if ui.add_enabled(selected, egui::Button::new("Delete")).clicked() { self.edit = Some(Edit::Delete) }
+                });
+
                 ui.menu_button("Scale...", |ui| {
                     if ui.button(Scale::CMajor.label_text(self.scale)).clicked() {
                         self.scale = Scale::CMajor
@@ -127,21 +705,111 @@ impl Showable<f32> for Grid {
                     self.timbre_open = !self.timbre_open;
                 };
 
+                ui.menu_button("Voices...", |ui| {
+                    for mode in [VoiceMode::Poly, VoiceMode::Mono, VoiceMode::Legato] {
+                        ui.radio_value(&mut self.voices.mode, mode, mode.label());
+                    }
+                    ui.add_enabled(
+                        self.voices.mode == VoiceMode::Poly,
+                        egui::Slider::new(&mut self.voices.polyphony, RangeInclusive::new(1, 16)).text("Polyphony"));
+                });
+
+                ui.menu_button("Arp...", |ui| {
+                    ui.checkbox(&mut self.arp.enabled, "Arpeggiate");
+                    ui.add_enabled_ui(self.arp.enabled, |ui| {
+                        ui.separator();
+                        for order in ArpOrder::ALL {
+                            ui.radio_value(&mut self.arp.order, order, order.label());
+                        }
+                        ui.separator();
+                        for rate in ArpRate::ALL {
+                            ui.radio_value(&mut self.arp.rate, rate, rate.label());
+                        }
+                        ui.separator();
+                        ui.add(egui::Slider::new(&mut self.arp.octaves, RangeInclusive::new(1, 4)).text("Octaves"));
+                    });
+                });
+
+                ui.menu_button("Swing...", |ui| {
+                    let mut own = self.swing.is_some();
+                    if ui.checkbox(&mut own, "Override global swing").changed() {
+                        self.swing = if own { Some(0.0) } else { None }
+                    }
+                    if let Some(swing) = self.swing.as_mut() {
+                        ui.add(egui::Slider::new(swing, RangeInclusive::new(0.0, 0.5)).text("Swing"));
+                    }
+                });
+
+                ui.menu_button("Chords...", |ui| {
+                    ui.checkbox(&mut self.chords.enabled, "Chord mode");
+                    ui.add_enabled_ui(self.chords.enabled, |ui| {
+                        ui.separator();
+                        for kind in ChordKind::ALL {
+                            ui.radio_value(&mut self.chords.kind, kind, kind.label());
+                        }
+                        ui.add_enabled_ui(self.chords.kind == ChordKind::Custom, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Degrees");
+                                ui.text_edit_singleline(&mut self.chords.custom)
+                                    .on_hover_text("Scale degrees above the root, like \"0 2 4 6\"");
+                            });
+                        });
+                        ui.separator();
+                        for voicing in Voicing::ALL {
+                            ui.radio_value(&mut self.chords.voicing, voicing, voicing.label());
+                        }
+                        ui.add(egui::Slider::new(&mut self.chords.inversion, RangeInclusive::new(0, 3)).text("Inversion"));
+                    });
+                });
+
                 if ui.button("Color").clicked() {
                     self.color = Self::random_color();
                 }
             });
 
             egui::MenuBar::new().ui(ui, |ui| {
+                if state.selected && state.recording {
+                    ui.colored_label(Color32::RED, "REC").on_hover_text("The keyboard plays this track");
+                }
+                ui.toggle_value(&mut self.muted, "M").on_hover_text("Mute");
                 ui.label("Volume");
                 ui.add(egui::Slider::new(&mut self.volume, RangeInclusive::new(0.0, 2.0)).show_value(false));
+
+                ui.separator();
+                for p in 0..PATTERNS {
+                    // Patterns with notes in them are in bold, and a queued one is highlighted
+                    let used = if p == self.pattern { self.notes.contains(&true) } else { self.patterns[p].notes.contains(&true) };
+                    let text = egui::RichText::new(pattern_name(p));
+                    let text = if used { text.strong() } else { text };
+                    let text = if self.queued == Some(p) { text.color(Color32::YELLOW) } else { text };
+                    let button = ui.selectable_label(p == self.pattern, text)
+                        .on_hover_text("While playing, the switch happens at the end of the loop");
+
+                    // While we're playing, wait for the end of the loop to switch
+                    if button.clicked() {
+                        if !state.playing {
+                            self.select_pattern(p)
+                        } else if p == self.pattern {
+                            self.queued = None
+                        } else {
+                            self.queued = Some(p)
+                        }
+                    }
+                }
             });
 
             egui::Frame::new().inner_margin(3).show(ui, |ui| {
-                self.draw_grid(ui, *cursor)
+                ui.horizontal(|ui| {
+                    self.draw_keys(ui);
+                    self.draw_grid(ui, state.cursor, state.audition)
+                });
             });
         });
 
+        if let Some(shown) = shown && shown.response.contains_pointer() && ctx.input(|i| i.pointer.any_pressed()) {
+            self.clicked = true
+        }
+
         if self.timbre_open {
             let mut topen = true;
             let mut name = self.name.clone();
@@ -153,4 +821,106 @@ impl Showable<f32> for Grid {
 
         self.open = open;
     }
-}
\ No newline at end of file
+}
+/// The cells in a straight line from one cell to another, both included
+fn line(from: usize, to: usize) -> Vec<usize> {
+    let size = LOOP_LENGTH as i32;
+    let (x0, y0) = (from as i32 % size, from as i32 / size);
+    let (x1, y1) = (to as i32 % size, to as i32 / size);
+    let steps = (x1 - x0).abs().max((y1 - y0).abs());
+    (0..=steps).map(|i| {
+        let t = if steps == 0 { 0.0 } else { i as f32 / steps as f32 };
+        let x = (x0 as f32 + (x1 - x0) as f32 * t).round() as i32;
+        let y = (y0 as f32 + (y1 - y0) as f32 * t).round() as i32;
+        (x + y * size) as usize
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_paste() {
+        let mut grid = Grid::new(Id::new("test"));
+        grid.toggle(0);
+        grid.toggle(17);
+        grid.nudges[17] = 20;
+        let clip = grid.copy(Selection::new((1, 1), (0, 0)));
+        assert_eq!(clip.notes, vec![true, false, false, true]);
+        assert_eq!(clip.nudges, vec![0, 0, 0, 20]);
+
+        // Pasting goes at the selection, and gets cut off at the edge of the grid
+        grid.selection = Some(Selection::new((15, 2), (15, 2)));
+        grid.paste(&clip);
+        assert!(grid.notes[15 + 2 * 16]);
+        assert!(!grid.notes[15 + 3 * 16]);
+        assert_eq!(grid.selection, Some(Selection::new((15, 2), (15, 3))));
+
+        grid.delete_selection();
+        assert!(!grid.notes[15 + 2 * 16]);
+        assert!(grid.notes[0] && grid.notes[17]);
+    }
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line(5, 5), vec![5]);
+        assert_eq!(line(0, 3), vec![0, 1, 2, 3]);
+        // Down a column, and diagonally up and to the left
+        assert_eq!(line(1, 33), vec![1, 17, 33]);
+        assert_eq!(line(34, 0), vec![34, 17, 0]);
+    }
+
+    #[test]
+    fn test_cell_time() {
+        let mut grid = Grid::new(Id::new("test"));
+        // Straight, the first step comes at the very end of the loop
+        assert_eq!(grid.cell_time(0, 0.0), 16.0);
+        assert_eq!(grid.cell_time(3, 0.0), 3.0);
+        // Swing pushes back the odd steps only
+        assert_eq!(grid.cell_time(2, 0.25), 2.0);
+        assert_eq!(grid.cell_time(3, 0.25), 3.25);
+        // An early nudge on the first step plays at the end of the loop before
+        grid.nudges[16] = -10;
+        assert_eq!(grid.cell_time(16, 0.0), 15.9);
+        grid.nudges[17] = 20;
+        assert_eq!(grid.cell_time(17, 0.25), 1.45);
+    }
+
+    #[test]
+    fn test_patterns() {
+        let mut grid = Grid::new(Id::new("test"));
+        grid.toggle(5);
+        grid.select_pattern(2);
+        assert_eq!(grid.pattern, 2);
+        assert!(!grid.notes[5]);
+        grid.toggle(7);
+
+        grid.select_pattern(0);
+        assert!(grid.notes[5] && !grid.notes[7]);
+        assert!(grid.get_pattern(2).notes[7]);
+        assert_eq!(grid.get_pattern(1), Pattern::default());
+
+        // Out of range does nothing
+        grid.select_pattern(PATTERNS);
+        assert_eq!(grid.pattern, 0);
+
+        // Loading a pattern finds the same one, or an empty one, rather than losing any
+        let mut other = Pattern::default();
+        other.notes[9] = true;
+        grid.load_pattern(&other);
+        assert_eq!(grid.pattern, 1);
+        assert!(grid.get_pattern(0).notes[5]);
+        grid.select_pattern(2);
+        grid.load_pattern(&other);
+        assert_eq!(grid.pattern, 1);
+        for p in 2..PATTERNS {
+            grid.select_pattern(p);
+            grid.toggle(p)
+        }
+        other.notes[10] = true;
+        grid.load_pattern(&other);
+        assert_eq!(grid.pattern, PATTERNS - 1);
+        assert_eq!(grid.notes, other.notes);
+    }
+}
@@ -1,11 +1,20 @@
 use std::fs;
 use std::ops::RangeInclusive;
 use std::path::Path;
-use eframe::egui;
-use eframe::egui::{Context, Id, TopBottomPanel};
-use crate::grid::Grid;
+use std::time::{Duration, Instant};
+use eframe::{egui, App, Frame};
+use eframe::egui::{Color32, Context, Id, Key, KeyboardShortcut, Modifiers, Pos2, Rangef, Rect, Sense, TopBottomPanel, Vec2, ViewportCommand};
+use crate::autosave;
+use crate::clip::Clip;
+use crate::dialog::Dialog;
+use crate::grid::{Edit, Grid, GridState};
+use crate::record::RecordMode;
+use crate::prefs::Prefs;
 use crate::saveload::PersistedTenori;
-use crate::Tenori;
+use crate::song::pattern_name;
+use crate::synth::MAX_VOICES;
+use crate::tenori::{SavedTrack, Tenori, LOOP_LENGTH, MAX_TEMPO, MIN_TEMPO};
+use crate::validate::LoadError;
 
 /// A trait for things that can be shown in a gui, given a Context.
 pub trait Showable<T> {
@@ -17,15 +26,41 @@ impl Tenori {
         TopBottomPanel::top("menu_panel").show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
                 ui.menu_button("File", |ui| {
-                    if ui.button("Load").clicked() && let Err(s) = self.load_from_file() {
-                        self.dialogs.push(s.into())
+                    if ui.button("New").clicked() {
+                        self.unless_dirty("Start a new song", |t| {
+                            t.new_song();
+                            t.mark_clean();
+                            autosave::remove_recovery();
+                            autosave::set_session_file(None)
+                        })
+                    }
+
+                    if ui.button("Load").clicked() {
+                        self.unless_dirty("Load", |t| if let Err(s) = t.load_from_file() {
+                            t.dialogs.push(s.into())
+                        })
                     }
 
-                    if ui.add_enabled(self.default_filename.is_some(), egui::Button::new("Save")).clicked() {
-                        let path = self.default_filename.as_ref().unwrap_or_else(|| unreachable!());
-                        if let Err(s) = self.save_to_file(path) {
-                            self.dialogs.push(s.into())
+                    ui.add_enabled_ui(!self.prefs.recent.is_empty(), |ui| ui.menu_button("Recent", |ui| {
+                        for path in self.prefs.recent.clone() {
+                            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+                            if ui.button(name).on_hover_text(path.display().to_string()).clicked() {
+                                self.unless_dirty("Load", move |t| if let Err(s) = t.open_file(&path) {
+                                    t.dialogs.push(s.into())
+                                })
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("Clear recent files").clicked() {
+                            self.prefs.recent.clear();
+                            self.save_prefs()
                         }
+                    }));
+
+                    if ui.add_enabled(self.default_filename.is_some(), egui::Button::new("Save")).clicked()
+                        && let Some(path) = self.default_filename.clone()
+                        && let Err(s) = self.save_to_file(path) {
+                        self.dialogs.push(s.into())
                     }
 
                     if ui.button("Save As...").clicked() && let Err(s) = self.save_as() {
@@ -33,58 +68,510 @@ impl Tenori {
                     }
                 });
 
+                ui.menu_button("Edit", |ui| {
+                    if ui.add_enabled(self.history.can_undo(), egui::Button::new("Undo").shortcut_text("Ctrl+Z")).clicked() {
+                        self.undo()
+                    }
+                    if ui.add_enabled(self.history.can_redo(), egui::Button::new("Redo").shortcut_text("Ctrl+Shift+Z")).clicked() {
+                        self.redo()
+                    }
+                });
+
+                ui.menu_button("Settings", |ui| {
+                    ui.add(egui::Slider::new(&mut self.steps_per_beat, RangeInclusive::new(1, 8)).text("Steps per beat"));
+                    ui.horizontal(|ui| {
+                        ui.label("Time signature");
+                        ui.add(egui::DragValue::new(&mut self.time_signature.beats).range(RangeInclusive::new(1, 16)));
+                        ui.label("/");
+                        for unit in [2, 4, 8, 16] {
+                            ui.selectable_value(&mut self.time_signature.unit, unit, unit.to_string());
+                        }
+                    });
+                    ui.separator();
+                    let prefs = self.prefs.clone();
+                    ui.checkbox(&mut self.prefs.metronome.enabled, "Metronome");
+                    ui.add(egui::Slider::new(&mut self.prefs.metronome.volume, RangeInclusive::new(0.0, 2.0)).text("Metronome volume"));
+                    ui.horizontal(|ui| {
+                        ui.label("Count-in");
+                        ui.selectable_value(&mut self.prefs.metronome.count_in, 0, "Off");
+                        ui.selectable_value(&mut self.prefs.metronome.count_in, 1, "1 bar");
+                        ui.selectable_value(&mut self.prefs.metronome.count_in, 2, "2 bars");
+                    });
+                    ui.checkbox(&mut self.prefs.audition, "Play cells when they're turned on");
+                    ui.separator();
+                    ui.label("Recording from the keyboard");
+                    ui.add(egui::Slider::new(&mut self.prefs.recorder.quantize, RangeInclusive::new(0.0, 1.0)).text("Quantize"));
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut self.prefs.recorder.mode, RecordMode::Overdub, "Overdub");
+                        ui.radio_value(&mut self.prefs.recorder.mode, RecordMode::Replace, "Replace");
+                    });
+                    ui.add(egui::Slider::new(&mut self.prefs.recorder.octave, RangeInclusive::new(-3, 3)).text("Octave"));
+                    ui.separator();
+                    ui.checkbox(&mut self.prefs.autosave.enabled, "Autosave")
+                        .on_hover_text("Keep a copy of the song to get back if the program crashes. It never writes over your own files.");
+                    ui.add_enabled(self.prefs.autosave.enabled, egui::Slider::new(&mut self.prefs.autosave.minutes, RangeInclusive::new(1, 30)).text("Minutes between autosaves"));
+                    if self.prefs != prefs { self.save_prefs() }
+                    ui.separator();
+                    let mut max_voices = self.synth.max_voices();
+                    let slider = egui::Slider::new(&mut max_voices, MAX_VOICES).text("Max voices");
+                    if ui.add(slider).changed() {
+                        self.synth.set_max_voices(max_voices)
+                    }
+                });
+
+                ui.toggle_value(&mut self.prefs.recorder.enabled, "Rec")
+                    .on_hover_text("Play the selected track from the keyboard (Z to M, Q to I), and record while playing (Ctrl+R)");
+
                 if ui.button("Add track").clicked() {
-                    let id = self.window_id();
-                    self.grids.push(Grid::new(id));
+                    let track = SavedTrack { grid: Grid::new(self.window_id()), song: vec![], scenes: vec![] };
+                    let index = self.grids.len();
+                    self.insert_track(index, track.clone());
+                    self.remember_track(true, index, track)
+                }
+
+                if ui.button("Song...").clicked() {
+                    self.song.open = !self.song.open
+                }
+
+                if ui.button("Scenes...").clicked() {
+                    self.scenes.open = !self.scenes.open
+                }
+
+                if ui.button("Tempo...").clicked() {
+                    self.tempo_lane.open = !self.tempo_lane.open
                 }
 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    ui.add(egui::Slider::new(&mut self.tempo, RangeInclusive::new(20, 180)));
+                    // Laid out right to left, so these go backwards
+                    for nudge in [1.0, 0.1, -0.1, -1.0] {
+                        if ui.small_button(format!("{nudge:+}")).clicked() { self.set_tempo(self.tempo + nudge) }
+                    }
+                    // Through set_tempo, so dragging rounds the same as everything else
+                    let mut tempo = self.tempo;
+                    let drag = egui::DragValue::new(&mut tempo)
+                        .range(RangeInclusive::new(MIN_TEMPO, MAX_TEMPO))
+                        .speed(0.1)
+                        .max_decimals(2)
+                        .suffix(" bpm");
+                    let changed = ui.add(drag).changed();
+                    let slider = egui::Slider::new(&mut tempo, RangeInclusive::new(MIN_TEMPO, MAX_TEMPO)).show_value(false);
+                    if ui.add(slider).changed() || changed { self.set_tempo(tempo) }
+                    if ui.button("Tap").on_hover_text("Click in time to set the tempo").clicked() { self.tap() }
+                    if self.tempo_lane.enabled {
+                        ui.monospace(format!("{:.1}", self.current_tempo()))
+                            .on_hover_text("The tempo lane's tempo right now");
+                    }
+                    ui.add(egui::Slider::new(&mut self.swing, RangeInclusive::new(0.0, 0.5)).text("Swing"));
+                    let pos = self.position();
+                    ui.monospace(format!("{}.{}.{}", pos.bar, pos.beat, pos.step))
+                        .on_hover_text("Bar . beat . step");
+
+                    if ui.button("|>").on_hover_text("Step forward (Right)").clicked() { self.step_by(true) }
                     if self.playing {
-                        if ui.button("||").clicked() { self.playing = false }
-                    } else if ui.button(">").clicked() { self.playing = true }
+                        if ui.button("||").on_hover_text("Pause (Space)").clicked() { self.playing = false }
+                    } else if ui.button(">").on_hover_text("Play (Space)").clicked() { self.start() }
+                    if ui.button("<|").on_hover_text("Step back (Left)").clicked() { self.step_by(false) }
+                    if ui.button("[]").on_hover_text("Stop (Escape)").clicked() { self.stop() }
+                    if ui.button("|<").on_hover_text("Rewind (Home)").clicked() { self.rewind() }
                 });
             })
         });
     }
 
+    /// The bar along the bottom, with the loop region and a ruler to click on to move around
+    fn transport(&mut self, ctx: &Context) {
+        TopBottomPanel::bottom("transport_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let before = self.loop_region;
+                ui.checkbox(&mut self.loop_region.enabled, "Loop").on_hover_text("Ctrl+L");
+
+                // Shown counting from 1, like the bar.beat.step display
+                let mut first = self.loop_region.first + 1;
+                let mut last = self.loop_region.last + 1;
+                ui.add(egui::DragValue::new(&mut first).range(RangeInclusive::new(1, LOOP_LENGTH)));
+                ui.label("to");
+                ui.add(egui::DragValue::new(&mut last).range(RangeInclusive::new(first, LOOP_LENGTH)));
+                self.loop_region.first = first - 1;
+                self.loop_region.last = last.max(first) - 1;
+                if self.loop_region != before { self.clamp_to_region() }
+
+                ui.separator();
+                self.ruler(ui);
+            });
+        });
+    }
+
+    /// A strip with a box for each step. The loop region is lit up, and clicking moves the
+    /// playhead to that step.
+    fn ruler(&mut self, ui: &mut egui::Ui) {
+        let width = 20.0 * LOOP_LENGTH as f32;
+        let (rect, response) = ui.allocate_exact_size(Vec2::new(width, 12.0), Sense::click());
+        let (first, last) = self.loop_steps();
+        for step in 0..LOOP_LENGTH {
+            let left = rect.left() + step as f32 * 20.0;
+            let cell = Rect::from_min_size(Pos2::new(left + 1.0, rect.top()), Vec2::new(18.0, rect.height()));
+            let lit = self.loop_region.enabled && step >= first && step <= last;
+            ui.painter().rect_filled(cell, 2.0, Color32::from_gray(if lit { 0x88 } else { 0x44 }));
+        }
+        ui.painter().vline(
+            rect.left() + self.ratio() * width,
+            Rangef::new(rect.top(), rect.bottom()),
+            (2.0, Color32::WHITE)
+        );
+
+        if response.clicked() && let Some(pos) = response.interact_pointer_pos() {
+            self.seek(((pos.x - rect.left()) / 20.0).floor().max(0.0) as u32)
+        }
+    }
+
+    /// Keyboard shortcuts for the transport, unless someone's typing into a text box
+    fn shortcuts(&mut self, ctx: &Context) {
+        if ctx.wants_keyboard_input() { return }
+        let pressed = |shortcut: KeyboardShortcut| ctx.input_mut(|i| i.consume_shortcut(&shortcut));
+        let key = |key: Key| KeyboardShortcut::new(Modifiers::NONE, key);
+
+        if pressed(key(Key::Space)) {
+            if self.playing { self.playing = false } else { self.start() }
+        }
+        if pressed(key(Key::Home)) { self.rewind() }
+        // Escape and the arrows go to a menu, popup or dialog that's open, or a widget
+        // with focus (like a slider), before us
+        let busy = egui::Popup::is_any_open(ctx) || !self.dialogs.is_empty() || ctx.memory(|m| m.focused().is_some());
+        if !busy {
+            if pressed(key(Key::Escape)) { self.stop() }
+            if pressed(key(Key::ArrowLeft)) { self.step_by(false) }
+            if pressed(key(Key::ArrowRight)) { self.step_by(true) }
+        }
+        // Shift+Z first, since Ctrl+Z would take it too
+        if pressed(KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z))
+            || pressed(KeyboardShortcut::new(Modifiers::COMMAND, Key::Y)) {
+            self.redo()
+        }
+        if pressed(KeyboardShortcut::new(Modifiers::COMMAND, Key::Z)) { self.undo() }
+        if pressed(KeyboardShortcut::new(Modifiers::COMMAND, Key::R)) {
+            self.prefs.recorder.enabled = !self.prefs.recorder.enabled
+        }
+        if pressed(KeyboardShortcut::new(Modifiers::COMMAND, Key::L)) {
+            self.loop_region.enabled = !self.loop_region.enabled;
+            self.clamp_to_region()
+        }
+    }
+
+    /// Copy, cut, paste or delete on one of the grids
+    fn apply_edit(&mut self, ctx: &Context, grid: usize, edit: Edit) {
+        let grid = &mut self.grids[grid];
+        match edit {
+            Edit::Copy | Edit::Cut => if let Some(clip) = grid.copy_selection() {
+                ctx.copy_text(clip.to_text());
+                if edit == Edit::Cut { grid.delete_selection() }
+                self.clipboard = Some(clip)
+            }
+            Edit::Paste(text) => {
+                // Text from the system clipboard wins, if it's a clip
+                let clip = text.as_deref().and_then(Clip::from_text).or(self.clipboard.clone());
+                if let Some(clip) = clip { grid.paste(&clip) }
+            }
+            Edit::Delete => grid.delete_selection()
+        }
+    }
+
+    /// The clipboard keys, and Delete, act on the selected grid
+    fn edit_keys(&mut self, ctx: &Context) {
+        if ctx.wants_keyboard_input() { return }
+        let edits: Vec<_> = ctx.input(|i| i.events.iter().filter_map(|e| match e {
+            egui::Event::Copy => Some(Edit::Copy),
+            egui::Event::Cut => Some(Edit::Cut),
+            egui::Event::Paste(text) => Some(Edit::Paste(Some(text.clone()))),
+            egui::Event::Key { key: Key::Delete | Key::Backspace, pressed: true, .. } => Some(Edit::Delete),
+            _ => None
+        }).collect());
+
+        let Some(grid) = self.selected_grid().map(|g| g.id) else { return };
+        let grid = self.grids.iter().position(|g| g.id == grid).unwrap_or(0);
+        for edit in edits {
+            self.apply_edit(ctx, grid, edit)
+        }
+    }
+
+    /// Play (and maybe record) notes from the keyboard, if it's turned on
+    fn keyboard(&mut self, ctx: &Context) {
+        if !self.prefs.recorder.enabled || ctx.wants_keyboard_input() { return }
+        let keys: Vec<_> = ctx.input(|i| i.events.iter().filter_map(|e| match e {
+            egui::Event::Key { key, pressed: true, repeat: false, modifiers, .. }
+                if !modifiers.command && !modifiers.alt => Some(*key),
+            _ => None
+        }).collect());
+
+        for key in keys {
+            if let Some(tone) = self.prefs.recorder.key_tone(key) {
+                self.record(tone)
+            }
+        }
+    }
+
     pub fn display_dialogs(&mut self, ctx: &Context) {
+        // Escape closes the dialog on top, like its close button does
+        if !self.dialogs.is_empty() && !egui::Popup::is_any_open(ctx)
+            && ctx.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape))
+            && let Some(d) = self.dialogs.last_mut() {
+            d.open = false
+        }
         for d in self.dialogs.iter_mut() {
             d.show(ctx, &());
         }
-        self.dialogs.retain(|d| d.1);
+        let chosen: Vec<_> = self.dialogs.iter_mut().filter_map(|d| d.chosen.take()).collect();
+        self.dialogs.retain(|d| d.open);
+        for action in chosen {
+            action(self)
+        }
+    }
+
+    /// Take a track out, remembering it so that it can be put back
+    fn close_track(&mut self, id: Id) {
+        let Some(i) = self.grids.iter().position(|g| g.id == id) else { return };
+        if let Some(track) = self.remove_track(i) {
+            self.remember_track(false, i, track)
+        }
+    }
+
+    /// Show a dialog, unless the same one is up already
+    fn ask(&mut self, dialog: Dialog) {
+        if !self.dialogs.iter().any(|d| d.message == dialog.message) {
+            self.dialogs.push(dialog)
+        }
+    }
+
+    /// Do something that throws away the current song, checking first if it isn't saved
+    fn unless_dirty(&mut self, go_ahead: &str, action: impl FnOnce(&mut Tenori) + 'static) {
+        if self.dirty {
+            self.ask(Dialog::confirm(format!("There are unsaved changes. {go_ahead} anyway, and lose them?"), go_ahead, action))
+        } else {
+            action(self)
+        }
+    }
+
+    /// The song as it goes in a file
+    pub fn to_text(&self) -> Result<String, String> {
+        toml::to_string(&PersistedTenori::from(self)).map_err(|e| e.to_string())
+    }
+
+    /// Take what we have now as saved. Whatever else changed this frame (like loading a
+    /// whole song) isn't an edit.
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+        self.history.skip = true
+    }
+
+    /// The window title: the file we're working on (if any), and whether it's changed
+    fn title(&self) -> String {
+        let file = self.default_filename.as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or("Untitled".to_string());
+        format!("{file}{} - Tenori-ish", if self.dirty { " *" } else { "" })
+    }
+
+    /// Call once, before the first frame: read our settings, and offer to get back the song
+    /// from last time if we crashed
+    pub fn start_session(&mut self) {
+        self.prefs = Prefs::load();
+        if let Some(text) = autosave::start_session() {
+            self.ask(Dialog::confirm(
+                "Tenori-ish didn't close properly last time. Get back the song from then?",
+                "Restore",
+                move |t| if let Err(s) = t.restore(&text) { t.dialogs.push(s.into()) }))
+        }
+    }
+
+    /// Load the autosaved song. It's not saved anywhere of the user's yet, so it counts as
+    /// unsaved, and we don't know a file to save it to.
+    fn restore(&mut self, text: &str) -> Result<(), String> {
+        let (persisted, repaired) = PersistedTenori::from_text(text).map_err(|e| format!("Couldn't read the autosaved song: {e}"))?;
+        persisted.apply_to(self);
+        self.report_repairs(&repaired);
+        self.default_filename = None;
+        autosave::set_session_file(None);
+        self.dirty = true;
+        Ok(())
     }
 
-    fn save_as(&self) -> Result<(), String> {
+    /// Write the recovery file, if it's time and there's something new to write
+    fn autosave(&mut self) {
+        let now = Instant::now();
+        if !self.dirty || !self.prefs.autosave.due(self.autosaved.0, now) { return }
+        self.autosaved.0 = now;
+        let Ok(text) = self.to_text() else { return };
+        if text == self.autosaved.1 { return }
+        match autosave::write_recovery(&text) {
+            Ok(()) => self.autosaved.1 = text,
+            Err(s) => self.dialogs.push(format!("Couldn't autosave: {s}").into())
+        }
+    }
+
+    /// Quitting with unsaved changes asks first
+    fn check_quit(&mut self, ctx: &Context) {
+        if self.quitting {
+            ctx.send_viewport_cmd(ViewportCommand::Close)
+        } else if ctx.input(|i| i.viewport().close_requested()) && self.dirty {
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            let mut dialog = Dialog::confirm("There are unsaved changes. Quit anyway, and lose them?", "Quit", |t| t.quitting = true);
+            if let Some(path) = self.default_filename.clone() {
+                dialog = dialog.with_choice("Save and quit", move |t| match t.save_to_file(&path) {
+                    Ok(()) => t.quitting = true,
+                    Err(s) => t.dialogs.push(s.into())
+                })
+            }
+            self.ask(dialog)
+        }
+    }
+
+    fn save_as(&mut self) -> Result<(), String> {
+        let name = self.default_filename.as_ref().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().to_string());
         if let Some(path) = rfd::FileDialog::new()
             .add_filter("Tenori files", &["tenori"])
-            .set_file_name("song.tenori").save_file() {
+            .set_file_name(name.unwrap_or("song.tenori".to_string())).save_file() {
             return self.save_to_file(path)
         }
         Ok(())
     }
 
-    fn save_to_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), String> {
-        let serialized = toml::to_string(&PersistedTenori::from(self)).map_err(|e| e.to_string())?;
-        fs::write(filename, serialized).map_err(|e| e.to_string())
+    /// Save to a file, which becomes the one that Save saves to
+    fn save_to_file<P: AsRef<Path>>(&mut self, filename: P) -> Result<(), String> {
+        let serialized = self.to_text()?;
+        fs::write(&filename, &serialized).map_err(|e| e.to_string())?;
+        self.dirty = false;
+        self.remember_file(filename.as_ref());
+        Ok(())
     }
 
     fn load_from_file(&mut self) -> Result<(), String> {
         if let Some(path) = rfd::FileDialog::new()
             .add_filter("Tenori files", &["tenori"])
             .pick_file() {
-            let serialized = fs::read_to_string(path).map_err(|e| e.to_string())?;
-            let persisted = toml::from_str::<PersistedTenori>(serialized.as_str()).map_err(|e| e.to_string())?;
-            persisted.apply_to(self);
+            return self.open_file(&path)
         }
         Ok(())
     }
 
+    /// Load a file, which becomes the one that Save saves to
+    pub fn open_file(&mut self, path: &Path) -> Result<(), String> {
+        let (persisted, repaired) = PersistedTenori::read(path).map_err(|e| format!("Couldn't load {}: {e}", path.display()))?;
+        persisted.apply_to(self);
+        self.report_repairs(&repaired);
+        self.mark_clean();
+        self.remember_file(path);
+        Ok(())
+    }
+
+    /// Say what was wrong with a song we loaded, that we put right
+    fn report_repairs(&mut self, repaired: &[LoadError]) {
+        const LISTED: usize = 10;
+        if repaired.is_empty() { return }
+        let mut message = "Some things in the song were wrong, and have been put right:".to_string();
+        for problem in repaired.iter().take(LISTED) {
+            message += &format!("\n• {problem}")
+        }
+        if repaired.len() > LISTED {
+            message += &format!("\n...and {} more", repaired.len() - LISTED)
+        }
+        let mut dialog = Dialog::from(message);
+        dialog.title = "Repaired".to_string();
+        self.dialogs.push(dialog)
+    }
+
+    /// Make a file the one to save to, and put it at the top of the recent files. It's
+    /// just been saved or loaded, so there's nothing to recover and the copy goes.
+    fn remember_file(&mut self, path: &Path) {
+        autosave::remove_recovery();
+        autosave::set_session_file(Some(path));
+        self.autosaved.1.clear();
+        self.default_filename = Some(path.to_path_buf());
+        self.prefs.add_recent(path.to_path_buf());
+        self.save_prefs()
+    }
+
+    fn save_prefs(&mut self) {
+        if let Err(s) = self.prefs.save() {
+            self.ask(format!("Couldn't save settings: {s}").into())
+        }
+    }
+
     fn display_grids(&mut self, ctx: &Context, cursor: &f32) {
+        let selected = self.selected_grid().map(|g| g.id);
         for g in self.grids.iter_mut() {
-            g.show(ctx, cursor)
+            let state = GridState {
+                cursor: *cursor,
+                playing: self.playing,
+                selected: Some(g.id) == selected,
+                recording: self.prefs.recorder.enabled,
+                audition: self.prefs.audition
+            };
+            g.show(ctx, &state)
+        }
+
+        self.play_auditions();
+        for i in 0..self.grids.len() {
+            if let Some(edit) = self.grids[i].edit.take() {
+                self.apply_edit(ctx, i, edit)
+            }
+        }
+
+        // Clicking on a grid makes it the one the keyboard plays
+        for g in self.grids.iter_mut().filter(|g| g.clicked) {
+            g.clicked = false;
+            self.selected = Some(g.id)
+        }
+
+        // Clearing or closing a track with notes in it checks first
+        for i in 0..self.grids.len() {
+            let grid = &mut self.grids[i];
+            let id = grid.id;
+            if std::mem::take(&mut grid.clearing) && grid.notes.contains(&true) {
+                let message = format!("Clear every note in {} pattern {}?", grid.name, pattern_name(grid.pattern));
+                self.ask(Dialog::confirm(message, "Clear", move |t| {
+                    if let Some(grid) = t.grids.iter_mut().find(|g| g.id == id) { grid.clear() }
+                }))
+            } else if !grid.open && grid.has_notes() {
+                grid.open = true;
+                let message = format!("Close {}, and lose all of its patterns?", grid.name);
+                self.ask(Dialog::confirm(message, "Close", move |t| t.close_track(id)))
+            }
+        }
+
+        // Backwards, so the positions of the ones still to go don't change
+        let closed: Vec<_> = self.grids.iter().rev().filter(|g| !g.open).map(|g| g.id).collect();
+        for id in closed {
+            self.close_track(id)
+        }
+    }
+
+    fn display_scenes(&mut self, ctx: &Context) {
+        if !self.scenes.open { return }
+        self.scenes.show(ctx, &());
+
+        if std::mem::take(&mut self.scenes.capture) {
+            self.capture_scene()
+        }
+        if let Some(scene) = self.scenes.launch.take() {
+            self.launch_scene(scene)
+        }
+    }
+
+    fn display_tempo_lane(&mut self, ctx: &Context) {
+        if !self.tempo_lane.open { return }
+        self.tempo_lane.show(ctx, &());
+    }
+
+    fn display_song(&mut self, ctx: &Context) {
+        if !self.song.open { return }
+        let was_enabled = self.song.enabled;
+        let names = self.grids.iter().map(|g| g.name.clone()).collect();
+        self.song.show(ctx, &names);
+
+        // Turning the song on starts it from the top
+        if self.song.enabled && !was_enabled {
+            self.rewind()
         }
-        self.grids.retain(|g| g.open);
     }
 
     /// Return a unique (among all the windows created since a file load) id string for a window.
@@ -96,8 +583,50 @@ impl Tenori {
 
 impl Showable<f32> for Tenori {
     fn show(&mut self, ctx: &Context, cursor: &f32) {
+        self.shortcuts(ctx);
+        self.keyboard(ctx);
+        self.edit_keys(ctx);
         self.menu(ctx);
+        self.transport(ctx);
         self.display_grids(ctx, cursor);
+        self.display_song(ctx);
+        self.display_scenes(ctx);
+        self.display_tempo_lane(ctx);
         self.display_dialogs(ctx);
     }
-}
\ No newline at end of file
+}
+impl App for Tenori {
+    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        // Before the tick, so a scene launched at the end of the loop can be undone
+        let before = self.track_states();
+        let (from, to) = self.tick();
+        let cursor = self.ratio();
+        self.clear_for_recording(from, to);
+
+        let pressed = ctx.input(|i| i.pointer.any_pressed() || i.events.iter().any(|e| matches!(e, egui::Event::Key { pressed: true, .. })));
+        let scrolled = ctx.input(|i| i.events.iter().any(|e| matches!(e, egui::Event::MouseWheel { .. })));
+        self.history.input(pressed, scrolled);
+        let settings = self.song_settings();
+        self.show(ctx, &cursor);
+        if !self.history.skip && self.song_settings() != settings { self.dirty = true }
+        self.remember_edits(&before);
+        self.autosave();
+        let title = self.title();
+        if ctx.input(|i| i.viewport().title.as_ref() != Some(&title)) {
+            ctx.send_viewport_cmd(ViewportCommand::Title(title))
+        }
+        self.check_quit(ctx);
+
+        for note in self.notes_between(from, to) {
+            self.play(note)
+        }
+        for click in self.clicks_between(from, to) {
+            self.play(click)
+        }
+        ctx.request_repaint_after(Duration::from_millis(17))
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        autosave::end_session()
+    }
+}
@@ -0,0 +1,316 @@
+use std::time::{Duration, Instant};
+use eframe::egui::Id;
+use crate::grid::{Grid, Pattern, TrackSettings};
+use crate::song::SongEntry;
+use crate::tempo::{LaneUnit, TempoPoint};
+use crate::tenori::{LoopRegion, SavedTrack, Tenori, TimeSignature};
+
+/// How many edits we keep to undo. Older ones are forgotten.
+pub const HISTORY_LENGTH: usize = 100;
+
+/// Settings changes on a track this close together are one edit, so that typing a name in
+/// is undone all at once. Cell changes further apart than this are never one edit, even
+/// in the same gesture.
+const EDIT_GAP: Duration = Duration::from_secs(1);
+
+/// A cell: whether it's lit, and its nudge
+pub type Cell = (bool, i8);
+
+/// An edit, which knows how to undo and redo itself
+#[derive(Clone)]
+pub enum Command {
+    /// Cells in one of a track's patterns changed, as (index, before, after)
+    Cells { track: Id, pattern: usize, changes: Vec<(usize, Cell, Cell)> },
+
+    /// A track's settings changed
+    Settings { track: Id, before: Box<TrackSettings>, after: Box<TrackSettings> },
+
+    /// A track was added or closed at a position
+    Track { added: bool, index: usize, track: Box<SavedTrack> }
+}
+
+/// What a track looked like at the start of a frame, to see what was edited by the end of it
+pub struct TrackState {
+    id: Id,
+    pattern: usize,
+    cells: Pattern,
+    settings: TrackSettings
+}
+
+impl From<&Grid> for TrackState {
+    fn from(value: &Grid) -> Self {
+        Self {
+            id: value.id,
+            pattern: value.pattern,
+            cells: value.get_pattern(value.pattern),
+            settings: value.settings()
+        }
+    }
+}
+
+/// The parts of the song that aren't in the history, to see whether the user changed any
+/// of them in a frame. The song, scenes and tempo lane are here without what playing them
+/// changes, like which entry of the song we're on.
+#[derive(PartialEq)]
+pub struct SongSettings {
+    tempo: f32,
+    steps_per_beat: u32,
+    time_signature: TimeSignature,
+    swing: f32,
+    max_voices: usize,
+    loop_region: LoopRegion,
+    song: (bool, Vec<SongEntry>),
+    tempo_lane: (bool, LaneUnit, Vec<TempoPoint>),
+    scenes: Vec<String>,
+    patterns: Vec<usize>
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+
+    /// Goes up with every click and key press, and when the scroll wheel starts turning,
+    /// so we can tell one drag from the next
+    gesture: u64,
+
+    /// Whether the scroll wheel has turned since the last click or key press
+    scrolling: bool,
+
+    /// The gesture and time of the last edit, to see whether the next one goes with it
+    last: Option<(u64, Instant)>,
+
+    /// Set when we've undone or redone something, or loaded a whole song, so the change
+    /// isn't taken for an edit
+    pub skip: bool
+}
+
+impl History {
+    /// Call every frame, with whether a mouse button or key went down, and whether the
+    /// scroll wheel turned
+    pub fn input(&mut self, pressed: bool, scrolled: bool) {
+        if pressed {
+            self.gesture += 1;
+            self.scrolling = false
+        }
+        if scrolled && !self.scrolling {
+            self.gesture += 1;
+            self.scrolling = true
+        }
+    }
+
+    /// Remember an edit. Cells changed in the same drag, or settings changed in the same
+    /// drag or close together, go in with the edit before.
+    pub fn push(&mut self, command: Command, now: Instant) {
+        let (same_gesture, recent) = match self.last {
+            Some((gesture, at)) => (gesture == self.gesture, now - at < EDIT_GAP),
+            None => (false, false)
+        };
+        self.last = Some((self.gesture, now));
+        self.redo.clear();
+
+        match (self.undo.last_mut(), command) {
+            (Some(Command::Cells { track, pattern, changes }), Command::Cells { track: t, pattern: p, changes: new })
+                if same_gesture && recent && *track == t && *pattern == p => {
+                for (n, before, after) in new {
+                    match changes.iter_mut().find(|c| c.0 == n) {
+                        Some(change) => change.2 = after,
+                        None => changes.push((n, before, after))
+                    }
+                }
+            }
+            (Some(Command::Settings { track, after, .. }), Command::Settings { track: t, after: a, .. })
+                if (same_gesture || recent) && *track == t => *after = a,
+            (_, command) => {
+                self.undo.push(command);
+                if self.undo.len() > HISTORY_LENGTH { self.undo.remove(0); }
+            }
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// The edits made to the tracks since the start of the frame. Tracks that were added or
+/// closed are left to whoever did it, and cells aren't compared if the pattern changed.
+pub fn edits(before: &[TrackState], grids: &[Grid]) -> Vec<Command> {
+    let mut commands = vec![];
+    for (state, grid) in before.iter().filter_map(|s| grids.iter().find(|g| g.id == s.id).map(|g| (s, g))) {
+        if state.pattern == grid.pattern {
+            let changes: Vec<_> = (0..grid.notes.len())
+                .map(|n| (n, (state.cells.notes[n], state.cells.nudges[n]), (grid.notes[n], grid.nudges[n])))
+                .filter(|(_, before, after)| before != after)
+                .collect();
+            if !changes.is_empty() {
+                commands.push(Command::Cells { track: grid.id, pattern: grid.pattern, changes })
+            }
+        }
+
+        let settings = grid.settings();
+        if settings != state.settings {
+            commands.push(Command::Settings { track: grid.id, before: Box::new(state.settings.clone()), after: Box::new(settings) })
+        }
+    }
+    commands
+}
+
+impl Tenori {
+    /// What the tracks look like now, to give to `remember_edits` later
+    pub fn track_states(&self) -> Vec<TrackState> {
+        self.grids.iter().map(TrackState::from).collect()
+    }
+
+    /// The song's settings now, to compare with later
+    pub fn song_settings(&self) -> SongSettings {
+        SongSettings {
+            tempo: self.tempo,
+            steps_per_beat: self.steps_per_beat,
+            time_signature: self.time_signature,
+            swing: self.swing,
+            max_voices: self.synth.max_voices(),
+            loop_region: self.loop_region,
+            song: (self.song.enabled, self.song.chain.clone()),
+            tempo_lane: (self.tempo_lane.enabled, self.tempo_lane.unit, self.tempo_lane.points.clone()),
+            scenes: self.scenes.scenes.iter().map(|s| s.name.clone()).collect(),
+            patterns: self.grids.iter().map(|g| g.pattern).collect()
+        }
+    }
+
+    /// Put whatever was edited since `before` into the history. Anything that goes in is
+    /// an unsaved change.
+    pub fn remember_edits(&mut self, before: &[TrackState]) {
+        if std::mem::take(&mut self.history.skip) { return }
+        let now = Instant::now();
+        for command in edits(before, &self.grids) {
+            self.history.push(command, now);
+            self.dirty = true
+        }
+    }
+
+    /// Remember a track being added or closed
+    pub fn remember_track(&mut self, added: bool, index: usize, track: SavedTrack) {
+        self.history.push(Command::Track { added, index, track: Box::new(track) }, Instant::now());
+        self.dirty = true
+    }
+
+    pub fn undo(&mut self) {
+        let Some(command) = self.history.undo.pop() else { return };
+        let command = self.apply_command(command, true);
+        self.history.redo.push(command);
+        self.history.last = None;
+        self.history.skip = true;
+        self.dirty = true
+    }
+
+    pub fn redo(&mut self) {
+        let Some(command) = self.history.redo.pop() else { return };
+        let command = self.apply_command(command, false);
+        self.history.undo.push(command);
+        self.history.last = None;
+        self.history.skip = true;
+        self.dirty = true
+    }
+
+    /// Undo or redo a command, and return it to go on the other stack
+    fn apply_command(&mut self, command: Command, undo: bool) -> Command {
+        match command {
+            Command::Cells { track, pattern, ref changes } => {
+                if let Some(grid) = self.grids.iter_mut().find(|g| g.id == track) {
+                    for &(n, before, after) in changes {
+                        grid.set_cell(pattern, n, if undo { before } else { after })
+                    }
+                }
+                command
+            }
+            Command::Settings { track, ref before, ref after } => {
+                if let Some(grid) = self.grids.iter_mut().find(|g| g.id == track) {
+                    grid.apply_settings(if undo { before } else { after })
+                }
+                command
+            }
+            // Undoing an add, or redoing a close, takes the track out again
+            Command::Track { added, index, track } if added == undo => {
+                let track = self.remove_track(index).map(Box::new).unwrap_or(track);
+                Command::Track { added, index, track }
+            }
+            Command::Track { added, index, track } => {
+                self.insert_track(index, (*track).clone());
+                Command::Track { added, index, track }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(track: &str, changes: &[usize]) -> Command {
+        let changes = changes.iter().map(|n| (*n, (false, 0), (true, 0))).collect();
+        Command::Cells { track: Id::new(track), pattern: 0, changes }
+    }
+
+    fn changes(command: &Command) -> usize {
+        match command {
+            Command::Cells { changes, .. } => changes.len(),
+            _ => 0
+        }
+    }
+
+    #[test]
+    fn test_merging() {
+        let mut history = History::default();
+        let now = Instant::now();
+
+        // One drag, on one track, is one edit
+        history.input(true, false);
+        history.push(cells("a", &[1]), now);
+        history.push(cells("a", &[2, 1]), now);
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(changes(&history.undo[0]), 2);
+
+        // Another track, or another click, is another
+        history.push(cells("b", &[1]), now);
+        history.input(true, false);
+        history.push(cells("b", &[2]), now);
+        assert_eq!(history.undo.len(), 3);
+
+        // Scrolling after a click is another, and so is the same drag much later
+        history.input(false, true);
+        history.push(cells("b", &[3]), now);
+        history.input(false, true);
+        history.push(cells("b", &[3]), now + Duration::from_millis(100));
+        assert_eq!(history.undo.len(), 4);
+        history.push(cells("b", &[3]), now + Duration::from_secs(60));
+        assert_eq!(history.undo.len(), 5);
+        let now = now + Duration::from_secs(60);
+
+        // Settings close together go together, even across key presses
+        let settings = Grid::new(Id::new("a")).settings();
+        let renamed = TrackSettings { name: "Bass".to_string(), ..settings.clone() };
+        let command = || Command::Settings { track: Id::new("a"), before: Box::new(settings.clone()), after: Box::new(renamed.clone()) };
+        history.push(command(), now);
+        history.input(true, false);
+        history.push(command(), now + Duration::from_millis(300));
+        assert_eq!(history.undo.len(), 6);
+        history.input(true, false);
+        history.push(command(), now + Duration::from_secs(5));
+        assert_eq!(history.undo.len(), 7);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut history = History::default();
+        for n in 0..HISTORY_LENGTH * 2 {
+            history.input(true, false);
+            history.push(cells("a", &[n % 256]), Instant::now());
+        }
+        assert_eq!(history.undo.len(), HISTORY_LENGTH);
+    }
+}
@@ -0,0 +1,26 @@
+pub mod gui;
+pub mod tenori;
+pub mod grid;
+pub mod noise;
+pub mod scale;
+pub mod saveload;
+pub mod dialog;
+pub mod envelope;
+pub mod timbre;
+pub mod synth;
+pub mod voice;
+pub mod arp;
+pub mod chord;
+pub mod song;
+pub mod scene;
+pub mod tempo;
+pub mod metronome;
+pub mod record;
+pub mod clip;
+pub mod transform;
+pub mod history;
+pub mod prefs;
+pub mod autosave;
+pub mod render;
+pub mod cli;
+pub mod validate;
@@ -1,39 +1,27 @@
-mod gui;
-mod tenori;
-mod grid;
-mod noise;
-mod scale;
-mod saveload;
-mod dialog;
-mod envelope;
-mod timbre;
-
-use std::time::Duration;
-use eframe::{App, Frame};
-use eframe::egui::Context;
-use crate::gui::Showable;
-use crate::tenori::Tenori;
+use tenori_ish::cli::{Args, USAGE};
+use tenori_ish::tenori::Tenori;
 
 #[tokio::main]
 async fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2)
+        }
+    };
+    if args.headless() {
+        std::process::exit(args.run())
+    }
+
     let native_options = eframe::NativeOptions::default();
-    eframe::run_native("Tenori-ish", native_options, Box::new(|_cc| {
-        Ok(Box::new(Tenori::default()))
+    eframe::run_native("Tenori-ish", native_options, Box::new(move |_cc| {
+        let mut tenori = Tenori::default();
+        tenori.start_session();
+        if let Some(file) = args.file.as_deref() && let Err(s) = tenori.open_file(file) {
+            tenori.dialogs.push(s.into())
+        }
+        if args.play { tenori.start() }
+        Ok(Box::new(tenori))
     })).expect("Error running application");
 }
-
-impl App for Tenori {
-    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
-        let play = self.tick();
-        let cursor = self.ratio();
-
-        self.show(ctx, &cursor);
-
-        if play {
-            for note in self.notes_for_beat() {
-                self.play(note)
-            }
-        }
-        ctx.request_repaint_after(Duration::from_millis(17))
-    }
-}
\ No newline at end of file
@@ -0,0 +1,45 @@
+use eframe::egui::Id;
+use serde::{Deserialize, Serialize};
+use crate::envelope::Envelope;
+use crate::noise::Note;
+use crate::timbre::Timbre;
+use crate::voice::Voices;
+
+/// A click on every beat, to play along with. It only ever goes to the speakers, never
+/// into anything we export.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metronome {
+    pub enabled: bool,
+
+    /// How loud, 0.0 .. 2.0, like a track's volume
+    pub volume: f32,
+
+    /// How many bars of clicks to play before starting from the top of the loop
+    pub count_in: u32
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            volume: 1.0,
+            count_in: 0
+        }
+    }
+}
+
+impl Metronome {
+    /// The note for one click; the first beat of a bar is accented, higher and louder
+    pub fn click(&self, accent: bool) -> Note {
+        // A short blip that dies away over 30ms
+        let envelope = Envelope { attack: 0.0, decay: 0.03, sustain: 0.0, hold: 0.0, release: 0.0 };
+        Note {
+            tone: if accent { 24 } else { 19 },
+            volume: if accent { self.volume } else { self.volume * 0.6 },
+            timbre: Timbre { sine: 1.0, square: 0.0, envelope, ..Timbre::default() },
+            track: Id::new("metronome"),
+            voices: Voices::default()
+        }
+    }
+}
@@ -1,6 +1,9 @@
+use eframe::egui::Id;
 use rodio::mixer::Mixer;
 use rodio::Source;
+use crate::synth::SAMPLE_RATE;
 use crate::timbre::Timbre;
+use crate::voice::{Voice, Voices};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Note {
@@ -11,22 +14,30 @@ pub struct Note {
     pub volume: f32,
 
     /// ADSR envelope
-    pub timbre: Timbre
+    pub timbre: Timbre,
+
+    /// The track this note came from, and how that track shares out voices
+    pub track: Id,
+    pub voices: Voices
 }
 
 /// The frequency for a given tone, in Hz.
 /// Tone 0 is A4, A above middle C, which is defined at 440 Hz.
 /// Moving up or down one is a single semitone, which means a
 /// change in `tone` of `12` is one octave.
-fn freq(tone: i32) -> f32 {
+pub fn freq(tone: i32) -> f32 {
     440.0 * 1.0595f32.powf(tone as f32)
 }
 
 impl Note {
-    pub fn play(self, mixer: &Mixer) {
+    /// Play this note as its own chain of rodio sources, on a voice from a `VoiceAllocator`.
+    /// The app sends its notes to a `Synth` instead; this stays so the benchmarks can
+    /// compare the two.
+    pub fn play(self, mixer: &Mixer, mut voice: Voice) {
+        voice.clamp_offset(self.timbre.envelope.hold_start(SAMPLE_RATE as f32));
         let freq = freq(self.tone);
-        let source = self.timbre.source(freq);
+        let source = self.timbre.source(freq, voice.offset());
         let source = source.amplify_normalized(self.volume);
-        mixer.add(source)
+        mixer.add(voice.wrap(source))
     }
 }
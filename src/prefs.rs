@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::autosave::Autosave;
+use crate::metronome::Metronome;
+use crate::record::Recorder;
+
+/// What we call our folder in the user's data directory
+const APP_DIR: &str = "tenori-ish";
+
+/// Our settings file, in the data directory
+const PREFS_FILE: &str = "settings.toml";
+
+/// Where we keep our own files (never the user's songs), like
+/// `~/.local/share/tenori-ish` on Linux. None if we can't tell where home is.
+pub fn data_dir() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        var("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        var("HOME")?.join("Library").join("Application Support")
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local").join("share")))?
+    };
+    Some(base.join(APP_DIR))
+}
+
+/// How many files the File menu remembers
+pub const RECENT_FILES: usize = 8;
+
+/// Settings for the program itself rather than for a song, so they aren't in song files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prefs {
+    pub autosave: Autosave,
+
+    /// Whether turning a cell on plays its note
+    pub audition: bool,
+
+    pub metronome: Metronome,
+
+    pub recorder: Recorder,
+
+    /// Files we've loaded or saved lately, most recent first
+    pub recent: Vec<PathBuf>
+}
+
+impl Default for Prefs {
+    fn default() -> Self {
+        Self {
+            autosave: Autosave::default(),
+            audition: true,
+            metronome: Metronome::default(),
+            recorder: Recorder::default(),
+            recent: vec![]
+        }
+    }
+}
+
+impl Prefs {
+    /// Put a file at the top of the recent files
+    pub fn add_recent(&mut self, path: PathBuf) {
+        self.recent.retain(|p| *p != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(RECENT_FILES)
+    }
+
+    /// The saved settings, or the defaults if there aren't any (or they won't read)
+    pub fn load() -> Self {
+        data_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(PREFS_FILE)).ok())
+            .and_then(|text| toml::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let dir = data_dir().ok_or("Couldn't find a folder to keep settings in")?;
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(dir.join(PREFS_FILE), text).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        // Settings files from older versions, missing things, still read
+        let prefs: Prefs = toml::from_str("").unwrap();
+        assert_eq!(prefs, Prefs::default());
+        let prefs: Prefs = toml::from_str("[autosave]\nenabled = false").unwrap();
+        assert_eq!(prefs.autosave, Autosave { enabled: false, ..Autosave::default() });
+        let prefs: Prefs = toml::from_str("audition = false\n[recorder]\noctave = 1").unwrap();
+        assert!(!prefs.audition && prefs.metronome == Metronome::default());
+        assert_eq!(prefs.recorder, Recorder { octave: 1, ..Recorder::default() });
+    }
+
+    #[test]
+    fn test_recent() {
+        let mut prefs = Prefs::default();
+        for n in 0..RECENT_FILES + 2 {
+            prefs.add_recent(format!("{n}.tenori").into())
+        }
+        prefs.add_recent("3.tenori".into());
+        assert_eq!(prefs.recent.len(), RECENT_FILES);
+        assert_eq!(prefs.recent[0], PathBuf::from("3.tenori"));
+        assert_eq!(prefs.recent[1], PathBuf::from("9.tenori"));
+        assert_eq!(prefs.recent.iter().filter(|p| p.as_path() == Path::new("3.tenori")).count(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+use eframe::egui::Key;
+use serde::{Deserialize, Serialize};
+use crate::scale::Scale;
+use crate::tenori::LOOP_LENGTH;
+
+/// Middle C, which is what the Z key plays with no octave shift
+const MIDDLE_C: i32 = -9;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RecordMode {
+    /// New notes are added to what's already there
+    #[default]
+    Overdub,
+    /// Once the first note goes in, each column is cleared as the playhead comes up to it,
+    /// so only new notes are left
+    Replace
+}
+
+/// Settings for playing and recording notes from the computer keyboard
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Recorder {
+    /// Whether the keyboard plays notes. Not saved, so we never start up recording.
+    #[serde(skip)]
+    pub enabled: bool,
+
+    /// How far (0.0 .. 1.0) recorded notes are pulled onto the nearest step. Anything left
+    /// over becomes the cell's nudge.
+    pub quantize: f32,
+
+    pub mode: RecordMode,
+
+    /// Octaves up or down from middle C
+    pub octave: i32
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quantize: 1.0,
+            mode: RecordMode::Overdub,
+            octave: 0
+        }
+    }
+}
+
+impl Recorder {
+    /// The tone a key plays, laid out like a piano: Z to M is the white and black notes of
+    /// one octave, and Q to I the one above.
+    pub fn key_tone(&self, key: Key) -> Option<i32> {
+        let semitone = match key {
+            Key::Z => 0, Key::S => 1, Key::X => 2, Key::D => 3, Key::C => 4, Key::V => 5,
+            Key::G => 6, Key::B => 7, Key::H => 8, Key::N => 9, Key::J => 10, Key::M => 11,
+            Key::Comma | Key::Q => 12, Key::Num2 => 13, Key::W => 14, Key::Num3 => 15,
+            Key::E => 16, Key::R => 17, Key::Num5 => 18, Key::T => 19, Key::Num6 => 20,
+            Key::Y => 21, Key::Num7 => 22, Key::U => 23, Key::I => 24,
+            _ => return None
+        };
+        Some(MIDDLE_C + self.octave * 12 + semitone)
+    }
+
+    /// Where a note played at `timer` goes: the nearest step, and what's left over (in
+    /// hundredths of a step, like `Grid::nudges`) after quantizing
+    pub fn quantize(&self, timer: f32) -> (u32, i8) {
+        let step = timer.round();
+        let off = (timer - step) * (1.0 - self.quantize.clamp(0.0, 1.0));
+        (step as u32, (off * 100.0).round().clamp(-50.0, 50.0) as i8)
+    }
+}
+
+/// The row of a grid in a scale whose note is closest to a tone
+pub fn nearest_row(scale: Scale, tone: i32) -> u32 {
+    (0..LOOP_LENGTH).min_by_key(|row| (scale.tone(*row) - tone).abs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        let full = Recorder::default();
+        assert_eq!(full.quantize(3.3), (3, 0));
+        assert_eq!(full.quantize(3.6), (4, 0));
+
+        let half = Recorder { quantize: 0.5, ..Recorder::default() };
+        assert_eq!(half.quantize(3.3), (3, 15));
+        assert_eq!(half.quantize(3.6), (4, -20));
+
+        let none = Recorder { quantize: 0.0, ..Recorder::default() };
+        assert_eq!(none.quantize(15.8), (16, -20));
+    }
+
+    #[test]
+    fn test_nearest_row() {
+        assert_eq!(nearest_row(Scale::CMajor, -9), 0);
+        // C# isn't in C major, so it goes to C or D
+        assert!([0, 1].contains(&nearest_row(Scale::CMajor, -8)));
+        assert_eq!(nearest_row(Scale::Chromatic, -8), 1);
+        // Way off the top goes to the top row
+        assert_eq!(nearest_row(Scale::CMajor, 40), 15);
+    }
+}
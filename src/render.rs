@@ -0,0 +1,112 @@
+use std::io::{self, Write};
+use crate::synth::{Synth, SAMPLE_RATE};
+use crate::tenori::Tenori;
+
+/// How many samples we render between looking for notes to start. Notes can only start on
+/// these boundaries, so it's kept small (about 1.5ms).
+const BLOCK: usize = 64;
+
+/// The longest we'll wait for the last notes to die away, in seconds
+const MAX_TAIL: f32 = 10.0;
+
+/// Play a song offline, as fast as we can, and return the samples. It goes round the loop
+/// `loops` times, or if that's None then once, or to the end of the song if it's on.
+/// The metronome is never in it.
+pub fn render(tenori: &mut Tenori, synth: &mut Synth, loops: Option<u32>) -> Vec<f32> {
+    let loops = loops.unwrap_or(if tenori.song.enabled && !tenori.song.chain.is_empty() { u32::MAX } else { 1 });
+    let (start, end) = tenori.loop_bounds();
+    let dt = BLOCK as f32 / SAMPLE_RATE as f32;
+
+    tenori.rewind();
+    tenori.playing = true;
+    // Just before the top, so the first column plays, like at the end of a count-in
+    tenori.timer = start - 0.001;
+
+    let mut samples = vec![];
+    let mut block = [0.0; BLOCK];
+    while tenori.playing && tenori.loops < loops {
+        let (from, to) = tenori.advance(dt);
+        let notes = if tenori.loops >= loops {
+            // The last time round: stop before the first column comes round again
+            if to < from && from < end - 0.001 { tenori.notes_between(from, end - 0.001) } else { vec![] }
+        } else {
+            tenori.notes_between(from, to)
+        };
+        for note in notes {
+            synth.note_on(note)
+        }
+        synth.render_block(&mut block);
+        samples.extend_from_slice(&block);
+    }
+
+    // Let the last notes ring out
+    let tail = (MAX_TAIL * SAMPLE_RATE as f32) as usize;
+    let stopped = samples.len();
+    while synth.sounding(None) > 0 && samples.len() - stopped < tail {
+        synth.render_block(&mut block);
+        samples.extend_from_slice(&block);
+    }
+
+    tenori.playing = false;
+    samples
+}
+
+/// Write samples out as a mono 16-bit WAV file
+pub fn write_wav(out: &mut impl Write, samples: &[f32]) -> io::Result<()> {
+    let data = (samples.len() * 2) as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // Mono
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Bytes per second
+    out.write_all(&2u16.to_le_bytes())?; // Bytes per sample
+    out.write_all(&16u16.to_le_bytes())?; // Bits per sample
+
+    out.write_all(b"data")?;
+    out.write_all(&data.to_le_bytes())?;
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use eframe::egui::Id;
+    use crate::grid::Grid;
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let (mut tenori, mut synth) = Tenori::headless();
+        let mut grid = Grid::new(Id::new("test"));
+        grid.toggle(15 * 16); // The bottom row, on the first step
+        tenori.grids.push(grid);
+
+        // Sixteen steps at 90bpm and four steps a beat is four beats, 8/3 of a second
+        let samples = render(&mut tenori, &mut synth, Some(1));
+        let seconds = samples.len() as f32 / SAMPLE_RATE as f32;
+        assert!(seconds >= 8.0 / 3.0 - 0.01, "{seconds}");
+        assert!(samples[..SAMPLE_RATE as usize / 10].iter().any(|s| s.abs() > 0.01));
+
+        // Without any notes it's silent
+        tenori.grids[0].clear();
+        assert!(render(&mut tenori, &mut synth, Some(2)).iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn test_wav() {
+        let mut out = vec![];
+        write_wav(&mut out, &[0.0, 1.0, -1.0]).unwrap();
+        assert_eq!(out.len(), 44 + 6);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(&out[40..44], &6u32.to_le_bytes());
+        assert_eq!(&out[44..], &[0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
@@ -1,71 +1,561 @@
+use std::fs;
+use std::path::Path;
 use eframe::egui::{Color32, Id};
 use serde::{Deserialize, Serialize};
-use crate::grid::Grid;
+use toml::{Table, Value};
+use crate::arp::Arpeggiator;
+use crate::chord::Chords;
+use crate::grid::{Grid, Pattern, Tool, PATTERNS};
+use crate::history::History;
 use crate::scale::Scale;
-use crate::tenori::Tenori;
+use crate::scene::{Scene, SceneTrack};
+use crate::song::Song;
+use crate::synth::MAX_VOICES;
+use crate::tempo::TempoLane;
+use crate::tenori::{LoopRegion, Tenori, TimeSignature, DEFAULT_STEPS_PER_BEAT, DEFAULT_TEMPO, LOOP_LENGTH, MAX_TEMPO, MIN_TEMPO};
 use crate::timbre::Timbre;
+use crate::validate::{LoadError, Repairs};
+use crate::voice::{Voices, DEFAULT_MAX_VOICES};
+
+/// The version of the file format we write. Files from before there were versions are
+/// version 0. When a change means older files need fixing up to read right, bump this and
+/// add a migration to `MIGRATIONS`. New fields that can just be left out don't need one, as
+/// long as they have a serde default.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Fixes up a file from one version of the format to the next, before it's read
+type Migration = fn(&mut Table) -> Result<(), String>;
+
+/// The migration from each version to the one after it, oldest first
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [v0_to_v1];
+
+/// Version 0 wrote the tempo as a whole number at first, and files from before there was
+/// a steps per beat setting played one step per beat
+fn v0_to_v1(file: &mut Table) -> Result<(), String> {
+    if let Some(Value::Integer(tempo)) = file.get("tempo") {
+        let tempo = *tempo as f64;
+        file.insert("tempo".to_string(), Value::Float(tempo));
+    }
+    file.entry("steps_per_beat").or_insert(Value::Integer(1));
+    Ok(())
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct PersistedTenori {
-    tempo: u32,
-    grids: Vec<PersistedGrid>
+    format_version: u32,
+    tempo: f32,
+    #[serde(default = "default_steps_per_beat")]
+    steps_per_beat: u32,
+    #[serde(default)]
+    time_signature: TimeSignature,
+    #[serde(default)]
+    swing: f32,
+    #[serde(default = "default_max_voices")]
+    max_voices: usize,
+    grids: Vec<PersistedGrid>,
+    #[serde(default)]
+    song: Song,
+    #[serde(default)]
+    tempo_lane: TempoLane,
+    #[serde(default)]
+    loop_region: LoopRegion,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    scenes: Vec<PersistedScene>
+}
+
+fn default_max_voices() -> usize {
+    DEFAULT_MAX_VOICES
+}
+
+fn default_steps_per_beat() -> u32 {
+    DEFAULT_STEPS_PER_BEAT
+}
+
+/// An empty song, which is what we start with
+impl Default for PersistedTenori {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            tempo: DEFAULT_TEMPO,
+            steps_per_beat: default_steps_per_beat(),
+            time_signature: TimeSignature::default(),
+            swing: 0.0,
+            max_voices: default_max_voices(),
+            grids: vec![],
+            song: Song::default(),
+            tempo_lane: TempoLane::default(),
+            loop_region: LoopRegion::default(),
+            scenes: vec![]
+        }
+    }
 }
 
 impl From<&Tenori> for PersistedTenori {
     fn from(value: &Tenori) -> Self {
         Self {
+            format_version: FORMAT_VERSION,
             tempo: value.tempo,
-            grids: value.grids.iter().map(PersistedGrid::from).collect()
+            steps_per_beat: value.steps_per_beat,
+            time_signature: value.time_signature,
+            swing: value.swing,
+            max_voices: value.synth.max_voices(),
+            grids: value.grids.iter().map(PersistedGrid::from).collect(),
+            song: value.song.clone(),
+            tempo_lane: value.tempo_lane.clone(),
+            loop_region: value.loop_region,
+            scenes: value.scenes.scenes.iter().map(PersistedScene::from).collect()
         }
     }
 }
 
 impl PersistedTenori {
+    /// Read a song file. Anything that was wrong with it but could be put right comes back
+    /// with it.
+    pub fn read(path: &Path) -> Result<(Self, Vec<LoadError>), LoadError> {
+        let serialized = fs::read_to_string(path).map_err(|e| LoadError::new(e.to_string()))?;
+        Self::from_text(&serialized)
+    }
+
+    /// Read a song from any version of the file format up to this one
+    pub fn from_text(text: &str) -> Result<(Self, Vec<LoadError>), LoadError> {
+        let mut file: Table = toml::from_str(text).map_err(|e| LoadError::parse(text, e))?;
+        let mut repairs = Repairs::new(text);
+        let version = match file.get("format_version") {
+            None => 0,
+            Some(Value::Integer(v)) if *v >= 0 => *v as u32,
+            Some(v) => return Err(repairs.error("format_version", format!("should be a whole number, not {v}")))
+        };
+        if version > FORMAT_VERSION {
+            return Err(repairs.error("format_version", format!("This file is from a newer version of Tenori-ish (format {version}), and this one only reads up to format {FORMAT_VERSION}")))
+        }
+
+        let mut persisted: Self = if version == FORMAT_VERSION {
+            // Straight from the text, so any errors say where they are
+            toml::from_str(text).map_err(|e| repairs.unreadable(text, e))?
+        } else {
+            for migrate in &MIGRATIONS[version as usize..] {
+                migrate(&mut file).map_err(LoadError::new)?
+            }
+            file.insert("format_version".to_string(), Value::Integer(FORMAT_VERSION as i64));
+            // Through text again, as a table doesn't know where anything is
+            let migrated = toml::to_string(&file).map_err(|e| LoadError::new(e.to_string()))?;
+            toml::from_str(&migrated).map_err(|e| repairs.unreadable(&migrated, e))?
+        };
+        persisted.repair(&mut repairs);
+        Ok((persisted, repairs.repaired))
+    }
+
+    /// Put right anything that's out of range, so it can't break anything once it's loaded
+    fn repair(&mut self, repairs: &mut Repairs) {
+        repairs.clamp("tempo", &mut self.tempo, MIN_TEMPO..=MAX_TEMPO);
+        repairs.clamp("steps_per_beat", &mut self.steps_per_beat, 1..=8);
+        repairs.clamp("time_signature.beats", &mut self.time_signature.beats, 1..=16);
+        if ![2, 4, 8, 16].contains(&self.time_signature.unit) {
+            repairs.note("time_signature.unit", format!("{} should be 2, 4, 8 or 16, so it's now 4", self.time_signature.unit));
+            self.time_signature.unit = 4
+        }
+        repairs.clamp("swing", &mut self.swing, 0.0..=0.5);
+        repairs.clamp("max_voices", &mut self.max_voices, MAX_VOICES);
+
+        for (n, grid) in self.grids.iter_mut().enumerate() {
+            grid.repair(&format!("grids[{n}]"), repairs)
+        }
+
+        for (n, entry) in self.song.chain.iter_mut().enumerate() {
+            for (track, pattern) in entry.patterns.iter_mut().enumerate() {
+                repairs.clamp(&format!("song.chain[{n}].patterns[{track}]"), pattern, 0..=PATTERNS - 1)
+            }
+            repairs.clamp(&format!("song.chain[{n}].repeats"), &mut entry.repeats, 1..=64)
+        }
+        for (n, point) in self.tempo_lane.points.iter_mut().enumerate() {
+            repairs.clamp(&format!("tempo_lane.points[{n}].tempo"), &mut point.tempo, MIN_TEMPO..=MAX_TEMPO)
+        }
+        if !self.tempo_lane.points.is_sorted_by_key(|p| p.at) {
+            repairs.note("tempo_lane.points", "should be in the order they come in, so they've been sorted");
+            self.tempo_lane.points.sort_by_key(|p| p.at)
+        }
+        repairs.clamp("loop_region.last", &mut self.loop_region.last, 0..=LOOP_LENGTH - 1);
+        repairs.clamp("loop_region.first", &mut self.loop_region.first, 0..=self.loop_region.last);
+
+        for (n, scene) in self.scenes.iter_mut().enumerate() {
+            for (track, part) in scene.tracks.iter_mut().enumerate() {
+                let field = format!("scenes[{n}].tracks[{track}]");
+                part.pattern.repair(&field, repairs);
+                repairs.clamp(&format!("{field}.volume"), &mut part.volume, 0.0..=2.0)
+            }
+        }
+    }
+
     pub fn apply_to(self, tenori: &mut Tenori) {
         tenori.grids = self.grids.into_iter().map(|g| g.into_grid(tenori.window_id())).collect();
         tenori.tempo = self.tempo;
+        tenori.steps_per_beat = self.steps_per_beat;
+        tenori.time_signature = self.time_signature;
+        tenori.swing = self.swing;
+        tenori.synth.set_max_voices(self.max_voices);
+        tenori.song = self.song;
+        tenori.scenes.scenes = self.scenes.into_iter().map(Scene::from).collect();
+        tenori.scenes.queued = None;
+        tenori.history = History::default();
+        tenori.apply_song();
+        tenori.tempo_lane = self.tempo_lane;
+        tenori.loop_region = self.loop_region;
         tenori.playing = false; // Start paused
-        tenori.timer = 0.0; // Start at the beginning of the loop
+        tenori.timer = tenori.loop_bounds().0; // Start at the beginning of the loop
+        tenori.loops = 0;
     }
 }
 
 #[derive(Serialize, Deserialize)]
 struct PersistedGrid {
     volume: f32,
+    #[serde(default)]
+    muted: bool,
     scale: Scale,
+    /// Pattern A, which is the only pattern in files from before there were more
     notes: String,
+    /// The nudged cells only, as (index, nudge) pairs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nudges: Vec<(usize, i8)>,
+    /// Patterns B and on, leaving off any empty ones at the end
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    patterns: Vec<PersistedPattern>,
+    /// Which pattern is selected
+    #[serde(default)]
+    pattern: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    swing: Option<f32>,
     name: String,
     timbre: Timbre,
-    color: (u8, u8, u8)
+    color: (u8, u8, u8),
+    #[serde(default)]
+    voices: Voices,
+    #[serde(default)]
+    arp: Arpeggiator,
+    #[serde(default)]
+    chords: Chords
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedPattern {
+    notes: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nudges: Vec<(usize, i8)>
+}
+
+impl From<Pattern> for PersistedPattern {
+    fn from(value: Pattern) -> Self {
+        Self {
+            notes: value.notes.iter().map(|n| if *n { '1' } else { '0' }).collect(),
+            nudges: value.nudges.iter().copied().enumerate().filter(|(_, n)| *n != 0).collect()
+        }
+    }
+}
+
+impl PersistedPattern {
+    fn repair(&mut self, field: &str, repairs: &mut Repairs) {
+        repair_pattern(field, &mut self.notes, &mut self.nudges, repairs)
+    }
+}
+
+/// A pattern has to have a cell for every step of every row, and nudges can only be so far
+fn repair_pattern(field: &str, notes: &mut String, nudges: &mut Vec<(usize, i8)>, repairs: &mut Repairs) {
+    let cells = (LOOP_LENGTH * LOOP_LENGTH) as usize;
+    if notes.chars().any(|c| c != '0' && c != '1') {
+        repairs.note(&format!("{field}.notes"), "should only be 0s and 1s, so anything else is now off");
+        *notes = notes.chars().map(|c| if c == '1' { '1' } else { '0' }).collect()
+    }
+    let length = notes.chars().count();
+    if length != cells {
+        let fixed = if length < cells { "the missing ones are off" } else { "the extra ones are gone" };
+        repairs.note(&format!("{field}.notes"), format!("has {length} cells rather than {cells}, so {fixed}"));
+        *notes = notes.chars().chain(std::iter::repeat('0')).take(cells).collect()
+    }
+
+    let field = format!("{field}.nudges");
+    for (n, (_, nudge)) in nudges.iter_mut().enumerate() {
+        repairs.clamp(&format!("{field}[{n}]"), nudge, -50..=50)
+    }
+    if nudges.iter().any(|(n, _)| *n >= cells) {
+        repairs.note(&field, format!("nudges cells past the last one ({}), so those are gone", cells - 1));
+        nudges.retain(|(n, _)| *n < cells)
+    }
+}
+
+impl From<PersistedPattern> for Pattern {
+    fn from(value: PersistedPattern) -> Self {
+        let notes: Vec<_> = value.notes.chars().map(|c| c == '1').collect();
+        let mut nudges = vec![0; notes.len()];
+        for (n, nudge) in value.nudges.into_iter().filter(|(n, _)| *n < notes.len()) {
+            nudges[n] = nudge
+        }
+        Self { notes, nudges }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedScene {
+    name: String,
+    tracks: Vec<PersistedSceneTrack>
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedSceneTrack {
+    #[serde(flatten)]
+    pattern: PersistedPattern,
+    volume: f32,
+    #[serde(default)]
+    muted: bool
+}
+
+impl From<&Scene> for PersistedScene {
+    fn from(value: &Scene) -> Self {
+        Self {
+            name: value.name.clone(),
+            tracks: value.tracks.iter().map(|t| PersistedSceneTrack {
+                pattern: t.pattern.clone().into(),
+                volume: t.volume,
+                muted: t.muted
+            }).collect()
+        }
+    }
+}
+
+impl From<PersistedScene> for Scene {
+    fn from(value: PersistedScene) -> Self {
+        Self {
+            name: value.name,
+            tracks: value.tracks.into_iter().map(|t| SceneTrack {
+                pattern: t.pattern.into(),
+                volume: t.volume,
+                muted: t.muted
+            }).collect()
+        }
+    }
 }
 
 impl From<&Grid> for PersistedGrid {
     fn from(value: &Grid) -> Self {
-        let notes: String = value.notes.iter().map(|n| if *n { '1' } else { '0' }).collect();
+        let PersistedPattern { notes, nudges } = value.get_pattern(0).into();
+        let mut patterns: Vec<_> = (1..PATTERNS).map(|p| value.get_pattern(p)).collect();
+        while patterns.last() == Some(&Pattern::default()) {
+            patterns.pop();
+        }
+
         Self {
             volume: value.volume,
+            muted: value.muted,
             scale: value.scale,
+            swing: value.swing,
+            nudges,
+            patterns: patterns.into_iter().map(PersistedPattern::from).collect(),
+            pattern: value.pattern,
             name: value.name.clone(),
             timbre: value.timbre,
             color: (value.color.r(), value.color.g(), value.color.b()),
+            voices: value.voices,
+            arp: value.arp,
+            chords: value.chords.clone(),
             notes
         }
     }
 }
 
 impl PersistedGrid {
+    fn repair(&mut self, field: &str, repairs: &mut Repairs) {
+        let at = |name: &str| format!("{field}.{name}");
+        repairs.clamp(&at("volume"), &mut self.volume, 0.0..=2.0);
+        if let Some(swing) = self.swing.as_mut() {
+            repairs.clamp(&at("swing"), swing, 0.0..=0.5)
+        }
+
+        repair_pattern(field, &mut self.notes, &mut self.nudges, repairs);
+        if self.patterns.len() > PATTERNS - 1 {
+            repairs.note(&at("patterns"), format!("there can only be {PATTERNS} patterns, so the rest are gone"));
+            self.patterns.truncate(PATTERNS - 1)
+        }
+        for (n, pattern) in self.patterns.iter_mut().enumerate() {
+            pattern.repair(&at(&format!("patterns[{n}]")), repairs)
+        }
+        repairs.clamp(&at("pattern"), &mut self.pattern, 0..=PATTERNS - 1);
+
+        let timbre = &mut self.timbre;
+        for (name, level) in [("sine", &mut timbre.sine), ("triangle", &mut timbre.triangle), ("square", &mut timbre.square),
+                              ("sawtooth", &mut timbre.sawtooth), ("noise", &mut timbre.noise), ("glide", &mut timbre.glide)] {
+            repairs.clamp(&at(&format!("timbre.{name}")), level, 0.0..=1.0)
+        }
+        let envelope = &mut timbre.envelope;
+        for (name, value, most) in [("attack", &mut envelope.attack, 1.0), ("decay", &mut envelope.decay, 1.0),
+                                    ("sustain", &mut envelope.sustain, 1.0), ("hold", &mut envelope.hold, 2.0),
+                                    ("release", &mut envelope.release, 1.0)] {
+            repairs.clamp(&at(&format!("timbre.envelope.{name}")), value, 0.0..=most)
+        }
+
+        repairs.clamp(&at("voices.polyphony"), &mut self.voices.polyphony, 1..=16);
+        repairs.clamp(&at("arp.octaves"), &mut self.arp.octaves, 1..=4);
+        repairs.clamp(&at("chords.inversion"), &mut self.chords.inversion, 0..=3);
+    }
+
     pub fn into_grid(self, id: Id) -> Grid {
-        let notes: Vec<_> = self.notes.chars().map(|c| c == '1').collect();
-        Grid {
+        let first = PersistedPattern { notes: self.notes, nudges: self.nudges };
+        let patterns = std::iter::once(first).chain(self.patterns).map(Pattern::from).collect();
+        let mut grid = Grid {
             volume: self.volume,
+            muted: self.muted,
             scale: self.scale,
+            swing: self.swing,
+            notes: vec![],
+            nudges: vec![],
+            pattern: 0,
+            queued: None,
+            patterns: vec![],
             name: self.name,
             timbre: self.timbre,
             open: true,
             timbre_open: false,
             color: Color32::from_rgb(self.color.0, self.color.1, self.color.2),
-            notes,
+            voices: self.voices,
+            arp: self.arp,
+            chords: self.chords,
+            played: vec![],
+            clicked: false,
+            audition: vec![],
+            tool: Tool::Draw,
+            selection: None,
+            drag: None,
+            edit: None,
+            clearing: false,
+            thin: 0.25,
             id
+        };
+        grid.set_patterns(patterns, self.pattern);
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_tempo() {
+        let (old, _) = PersistedTenori::from_text("tempo = 90\ngrids = []").unwrap();
+        assert_eq!(old.tempo, 90.0);
+        let (new, _) = PersistedTenori::from_text("tempo = 92.5\ngrids = []").unwrap();
+        assert_eq!(new.tempo, 92.5);
+    }
+
+    #[test]
+    fn test_versions() {
+        // Unversioned files are from before steps per beat, which were one step a beat
+        let (old, _) = PersistedTenori::from_text("tempo = 90\ngrids = []").unwrap();
+        assert_eq!((old.format_version, old.steps_per_beat), (FORMAT_VERSION, 1));
+        let (new, _) = PersistedTenori::from_text(&format!("format_version = {FORMAT_VERSION}\ntempo = 90.0\ngrids = []")).unwrap();
+        assert_eq!(new.steps_per_beat, DEFAULT_STEPS_PER_BEAT);
+
+        let newer = format!("format_version = {}\ntempo = 90.0\ngrids = []", FORMAT_VERSION + 1);
+        assert!(PersistedTenori::from_text(&newer).is_err_and(|e| e.reason.contains("newer version") && e.line == Some(1)));
+    }
+
+    /// Every file in tests/fixtures has to keep loading. They're named for the version of
+    /// the format they're in ("v0-..."), and there has to be at least one for every version.
+    #[test]
+    fn test_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+        let mut versions = vec![];
+        for path in fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()) {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let version: u32 = name.strip_prefix('v').and_then(|n| n.split('-').next()?.parse().ok())
+                .unwrap_or_else(|| panic!("{name} should start with its format version, like v0-"));
+            versions.push(version);
+
+            let (persisted, repaired) = PersistedTenori::read(&path).unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert_eq!(repaired, vec![], "{name}");
+            let (mut tenori, _) = Tenori::headless();
+            persisted.apply_to(&mut tenori);
+            assert!(!tenori.grids.is_empty(), "{name}");
+
+            // Saving it again writes the current format, which reads back the same
+            let saved = tenori.to_text().unwrap();
+            assert!(saved.starts_with(&format!("format_version = {FORMAT_VERSION}\n")), "{name}");
+            PersistedTenori::from_text(&saved).unwrap().0.apply_to(&mut tenori);
+            assert_eq!(tenori.to_text().unwrap(), saved, "{name}");
+        }
+        for version in 0..=FORMAT_VERSION {
+            assert!(versions.contains(&version), "There's no fixture for format version {version}")
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_repairs() {
+        let text = format!("format_version = {FORMAT_VERSION}
+tempo = 1000.0
+max_voices = 2
+
+[[grids]]
+volume = 1.0
+scale = \"CMajor\"
+notes = \"{}\"
+name = \"Fine\"
+color = [0, 0, 0]
+
+[grids.timbre]
+sine = 1.0
+
+[[grids]]
+volume = 5.0
+scale = \"CMajor\"
+notes = \"0101x\"
+nudges = [[300, 1], [0, 90]]
+name = \"Broken\"
+color = [0, 0, 0]
+
+[grids.timbre]
+sine = -1.0
+
+[tempo_lane]
+points = [{{ at = 4, tempo = 500.0, change = \"Step\" }}, {{ at = 1, tempo = 100.0, change = \"Step\" }}]
+", "0".repeat(256));
+        let (persisted, repaired) = PersistedTenori::from_text(&text).unwrap();
+        let found: Vec<_> = repaired.iter().map(|e| (e.line, e.field.as_deref())).collect();
+        assert_eq!(found, vec![
+            (Some(2), Some("tempo")),
+            (Some(3), Some("max_voices")),
+            (Some(16), Some("grids[1].volume")),
+            (Some(18), Some("grids[1].notes")),
+            (Some(18), Some("grids[1].notes")),
+            (Some(19), Some("grids[1].nudges[1]")),
+            (Some(19), Some("grids[1].nudges")),
+            (Some(24), Some("grids[1].timbre.sine")),
+            (Some(27), Some("tempo_lane.points[0].tempo")),
+            (Some(27), Some("tempo_lane.points"))
+        ]);
+
+        let (mut tenori, _) = Tenori::headless();
+        persisted.apply_to(&mut tenori);
+        assert_eq!((tenori.tempo, tenori.synth.max_voices()), (MAX_TEMPO, *MAX_VOICES.start()));
+        let points: Vec<_> = tenori.tempo_lane.points.iter().map(|p| (p.at, p.tempo)).collect();
+        assert_eq!(points, vec![(1, 100.0), (4, MAX_TEMPO)]);
+        let broken = &tenori.grids[1];
+        assert_eq!((broken.volume, broken.timbre.sine), (2.0, 0.0));
+        assert_eq!(broken.notes.len(), 256);
+        assert_eq!(&broken.notes[..5], &[false, true, false, true, false]);
+        assert_eq!(broken.nudges[0], 50);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = PersistedTenori::from_text("tempo = 90.0\ngrids = [\n").err().unwrap();
+        assert_eq!(error.line, Some(3));
+        let error = PersistedTenori::from_text(&format!("format_version = {FORMAT_VERSION}\ngrids = []\ntempo = \"fast\"")).err().unwrap();
+        assert_eq!(error.line, Some(3));
+        assert!(error.to_string().starts_with("line 3, tempo: "), "{error}");
+        assert!(PersistedTenori::from_text("tempo = 90.0").is_err_and(|e| e.reason.contains("grids")));
+
+        // Files from before there were versions are brought up to date first, and still
+        // say where the problem is in the file itself
+        let v0 = include_str!("../tests/fixtures/v0-song-scenes-tempo-lane.tenori").replace("volume = 0.7", "volume = \"loud\"");
+        let error = PersistedTenori::from_text(&v0).err().unwrap();
+        assert_eq!((error.line, error.field.as_deref()), (Some(59), Some("grids[1].volume")));
+    }
+}
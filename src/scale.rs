@@ -38,4 +38,20 @@ impl Scale {
             tones[row]
         }
     }
+
+    /// How many rows it takes this scale to go up an octave
+    pub fn notes_per_octave(self) -> i32 {
+        match self {
+            Scale::CMajor | Scale::CMinor => 7,
+            Scale::Chromatic => 12,
+            Scale::Pentatonic => 5
+        }
+    }
+
+    /// Like `tone`, but carries on up (or down) past the ends of the grid by octaves
+    pub fn degree(self, degree: i32) -> i32 {
+        let n = self.notes_per_octave();
+        let octave = degree.div_euclid(n);
+        self.tone(degree.rem_euclid(n) as u32) + octave * 12
+    }
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+use eframe::egui;
+use eframe::egui::{Context, Window};
+use crate::grid::Pattern;
+use crate::gui::Showable;
+
+/// One track's part of a scene
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneTrack {
+    pub pattern: Pattern,
+    pub volume: f32,
+    pub muted: bool
+}
+
+/// A snapshot of every track's notes, volume and mute, which can be brought back all at once
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub name: String,
+
+    /// In the same order as the tracks
+    pub tracks: Vec<SceneTrack>
+}
+
+/// All the scenes we have, and the window for launching them
+#[derive(Default)]
+pub struct Scenes {
+    pub scenes: Vec<Scene>,
+
+    /// Whether the scenes window is showing
+    pub open: bool,
+
+    /// The scene waiting for the end of the loop to be launched
+    pub queued: Option<usize>,
+
+    /// Set by the window when the user asks to capture a new scene or launch one; the
+    /// caller takes these and does the work, since it has the tracks.
+    pub capture: bool,
+    pub launch: Option<usize>
+}
+
+impl Scenes {
+    /// Forget about a track that's been closed, so the ones after it keep their parts.
+    /// Returns what each scene had for it, to give back to `insert_track`.
+    pub fn remove_track(&mut self, track: usize) -> Vec<Option<SceneTrack>> {
+        self.scenes.iter_mut()
+            .map(|s| (track < s.tracks.len()).then(|| s.tracks.remove(track)))
+            .collect()
+    }
+
+    /// Put back a track that `remove_track` took out
+    pub fn insert_track(&mut self, track: usize, parts: &[Option<SceneTrack>]) {
+        for (scene, part) in self.scenes.iter_mut().zip(parts) {
+            if let Some(part) = part && track <= scene.tracks.len() {
+                scene.tracks.insert(track, part.clone())
+            }
+        }
+    }
+}
+
+impl Showable<()> for Scenes {
+    fn show(&mut self, ctx: &Context, _state: &()) {
+        let mut open = true;
+        let window = Window::new("Scenes")
+            .open(&mut open)
+            .resizable([false, false]);
+
+        window.show(ctx, |ui| {
+            let mut remove = None;
+            egui::Grid::new("scenes").show(ui, |ui| {
+                for (i, scene) in self.scenes.iter_mut().enumerate() {
+                    let label = if self.queued == Some(i) { "Queued..." } else { "Launch" };
+                    if ui.button(label).clicked() { self.launch = Some(i) }
+                    ui.text_edit_singleline(&mut scene.name);
+                    if ui.button("x").clicked() { remove = Some(i) }
+                    ui.end_row();
+                }
+            });
+
+            if let Some(i) = remove {
+                self.scenes.remove(i);
+                self.queued = None;
+            }
+
+            if ui.button("Capture").on_hover_text("Save every track's notes, volume and mute as a new scene").clicked() {
+                self.capture = true
+            }
+        });
+
+        self.open = open;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(volume: f32) -> SceneTrack {
+        SceneTrack { pattern: Pattern::default(), volume, muted: false }
+    }
+
+    #[test]
+    fn test_remove_track() {
+        let mut scenes = Scenes::default();
+        scenes.scenes.push(Scene { name: "one".to_string(), tracks: vec![track(0.0), track(1.0), track(2.0)] });
+        scenes.scenes.push(Scene { name: "two".to_string(), tracks: vec![track(0.0)] });
+        scenes.remove_track(1);
+        assert_eq!(scenes.scenes[0].tracks, vec![track(0.0), track(2.0)]);
+        assert_eq!(scenes.scenes[1].tracks, vec![track(0.0)]);
+    }
+}
@@ -0,0 +1,193 @@
+use eframe::egui;
+use eframe::egui::{Context, RichText, Window};
+use serde::{Deserialize, Serialize};
+use crate::grid::PATTERNS;
+use crate::gui::Showable;
+
+/// The letter for a pattern: A, B, C...
+pub fn pattern_name(pattern: usize) -> String {
+    ((b'A' + pattern as u8) as char).to_string()
+}
+
+/// One row of the song: which pattern each track plays, and for how many loops
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SongEntry {
+    /// Which pattern each track plays, in the same order as the tracks. Tracks past the
+    /// end of this play pattern A.
+    pub patterns: Vec<usize>,
+
+    /// How many times through the loop this entry lasts
+    pub repeats: u32
+}
+
+/// A song, as a chain of entries that are each played for some number of loops
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Song {
+    /// Whether we're playing the song, or just looping whatever patterns are selected
+    pub enabled: bool,
+    pub chain: Vec<SongEntry>,
+
+    /// Whether the arranger window is showing
+    #[serde(skip)]
+    pub open: bool,
+
+    /// Which entry we're on, and how many times we've looped it
+    #[serde(skip)]
+    pub entry: usize,
+    #[serde(skip)]
+    pub repeat: u32
+}
+
+impl Song {
+    /// Go back to the start of the song
+    pub fn rewind(&mut self) {
+        self.entry = 0;
+        self.repeat = 0;
+    }
+
+    /// Move along the chain at the end of a loop. Returns false if that was the end of the song.
+    pub fn advance(&mut self) -> bool {
+        let Some(entry) = self.chain.get(self.entry) else { return true };
+        self.repeat += 1;
+        if self.repeat >= entry.repeats.max(1) {
+            self.repeat = 0;
+            self.entry += 1;
+            if self.entry >= self.chain.len() {
+                self.entry = 0;
+                return false
+            }
+        }
+        true
+    }
+
+    /// Which pattern a track should be playing right now, if there's a song to play
+    pub fn pattern(&self, track: usize) -> Option<usize> {
+        self.chain.get(self.entry).map(|e| e.patterns.get(track).copied().unwrap_or(0))
+    }
+
+    /// Forget about a track that's been closed, so the ones after it keep their patterns.
+    /// Returns what each entry had for it, to give back to `insert_track`.
+    pub fn remove_track(&mut self, track: usize) -> Vec<Option<usize>> {
+        self.chain.iter_mut()
+            .map(|e| (track < e.patterns.len()).then(|| e.patterns.remove(track)))
+            .collect()
+    }
+
+    /// Put back a track that `remove_track` took out
+    pub fn insert_track(&mut self, track: usize, patterns: &[Option<usize>]) {
+        for (entry, pattern) in self.chain.iter_mut().zip(patterns) {
+            if let Some(pattern) = pattern && track <= entry.patterns.len() {
+                entry.patterns.insert(track, *pattern)
+            }
+        }
+    }
+}
+
+/// The arranger window, given the names of the tracks
+impl Showable<Vec<String>> for Song {
+    fn show(&mut self, ctx: &Context, tracks: &Vec<String>) {
+        let mut open = true;
+        let window = Window::new("Song")
+            .open(&mut open)
+            .resizable([false, false]);
+
+        window.show(ctx, |ui| {
+            ui.checkbox(&mut self.enabled, "Play the song");
+
+            // What to do after drawing: move a row up (or down, with the row below) or delete it
+            let mut swap = None;
+            let mut remove = None;
+            egui::Grid::new("song").striped(true).show(ui, |ui| {
+                ui.label("");
+                for name in tracks {
+                    ui.label(name);
+                }
+                ui.label("Repeats");
+                ui.end_row();
+
+                let len = self.chain.len();
+                for (i, entry) in self.chain.iter_mut().enumerate() {
+                    let label = RichText::new(format!("{}", i + 1));
+                    ui.label(if self.enabled && i == self.entry { label.strong() } else { label });
+
+                    if entry.patterns.len() < tracks.len() {
+                        entry.patterns.resize(tracks.len(), 0)
+                    }
+                    for t in 0..tracks.len() {
+                        egui::ComboBox::from_id_salt(("song", i, t))
+                            .width(40.0)
+                            .selected_text(pattern_name(entry.patterns[t]))
+                            .show_ui(ui, |ui| {
+                                for p in 0..PATTERNS {
+                                    ui.selectable_value(&mut entry.patterns[t], p, pattern_name(p));
+                                }
+                            });
+                    }
+
+                    ui.add(egui::DragValue::new(&mut entry.repeats).range(1..=64));
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(i > 0, egui::Button::new("^")).clicked() { swap = Some(i - 1) }
+                        if ui.add_enabled(i + 1 < len, egui::Button::new("v")).clicked() { swap = Some(i) }
+                        if ui.button("x").clicked() { remove = Some(i) }
+                    });
+                    ui.end_row();
+                }
+            });
+
+            if let Some(i) = swap { self.chain.swap(i, i + 1) }
+            if let Some(i) = remove { self.chain.remove(i); }
+            if self.entry >= self.chain.len() { self.rewind() }
+
+            if ui.button("Add").clicked() {
+                // Start from a copy of the last row, which is usually what you want next
+                let entry = self.chain.last().cloned().unwrap_or(SongEntry {
+                    patterns: vec![0; tracks.len()],
+                    repeats: 1
+                });
+                self.chain.push(entry)
+            }
+        });
+
+        self.open = open;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(repeats: &[u32]) -> Song {
+        Song {
+            enabled: true,
+            chain: repeats.iter().enumerate().map(|(i, r)| SongEntry { patterns: vec![i, 0], repeats: *r }).collect(),
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn test_advance() {
+        let mut s = song(&[2, 1]);
+        assert_eq!(s.pattern(0), Some(0));
+        assert!(s.advance());
+        assert_eq!(s.pattern(0), Some(0));
+        assert!(s.advance());
+        assert_eq!(s.pattern(0), Some(1));
+        // Tracks the entry doesn't mention play pattern A
+        assert_eq!(s.pattern(5), Some(0));
+        assert!(!s.advance());
+        assert_eq!(s.pattern(0), Some(0));
+
+        assert_eq!(Song::default().pattern(0), None);
+        assert!(Song::default().advance());
+    }
+
+    #[test]
+    fn test_remove_track() {
+        let mut s = song(&[1, 1]);
+        s.remove_track(0);
+        assert_eq!(s.chain[1].patterns, vec![0]);
+        s.remove_track(3);
+        assert_eq!(s.chain[1].patterns, vec![0]);
+    }
+}
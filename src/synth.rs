@@ -0,0 +1,501 @@
+use std::f32::consts::TAU;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+use eframe::egui::Id;
+use rodio::{ChannelCount, SampleRate, Source};
+use crate::noise::{freq, Note};
+use crate::timbre::{GlideMode, Timbre};
+use crate::voice::{victim, LevelMeter, VoiceMode, DEFAULT_MAX_VOICES, STEAL_FADE};
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// How many voices the synth owns. This is also the highest the global voice limit can go.
+pub const POOL_SIZE: usize = 128;
+
+/// What the global voice limit can be set to
+pub const MAX_VOICES: RangeInclusive<usize> = 4..=POOL_SIZE;
+
+/// How many samples we render at a time
+const BLOCK: usize = 256;
+
+/// One of the synth's voices. These are never created or destroyed while playing, just
+/// switched on and off as notes come and go.
+#[derive(Copy, Clone)]
+struct PoolVoice {
+    active: bool,
+    stolen: bool,
+    track: Id,
+    serial: u64,
+    timbre: Timbre,
+    gain: f32,
+
+    // Oscillator state: every waveform shares one phase (0.0 .. 1.0), plus the noise generator
+    phase: f32,
+    step: f32,
+    noise: u32,
+
+    // Glide state: the step we're sliding towards, how much to multiply the step by each
+    // sample to get there, and how many samples are left
+    target: f32,
+    glide_mul: f32,
+    glide_left: usize,
+
+    // Envelope state: how many samples in we are, and how much of the steal fade is left
+    elapsed: usize,
+    fade: usize,
+
+    // Level meter, so we know which voices are in their tails
+    meter: LevelMeter
+}
+
+impl Default for PoolVoice {
+    fn default() -> Self {
+        Self {
+            active: false,
+            stolen: false,
+            track: Id::NULL,
+            serial: 0,
+            timbre: Timbre::default(),
+            gain: 0.0,
+            phase: 0.0,
+            step: 0.0,
+            noise: 1,
+            target: 0.0,
+            glide_mul: 1.0,
+            glide_left: 0,
+            elapsed: 0,
+            fade: STEAL_FADE,
+            meter: LevelMeter::default()
+        }
+    }
+}
+
+impl PoolVoice {
+    fn sounding(&self) -> bool {
+        self.active && !self.stolen
+    }
+
+    fn level(&self) -> f32 {
+        if self.elapsed == 0 { 1.0 } else { self.meter.level() }
+    }
+
+    fn start(&mut self, note: &Note, serial: u64) {
+        *self = Self {
+            active: true,
+            track: note.track,
+            serial,
+            noise: serial as u32 | 1,
+            ..Self::default()
+        };
+        self.retune(note)
+    }
+
+    /// Change the note this voice is playing without restarting it
+    fn retune(&mut self, note: &Note) {
+        self.timbre = note.timbre;
+        self.gain = gain(note.volume);
+        self.target = freq(note.tone) / SAMPLE_RATE as f32;
+        self.step = self.target;
+        self.glide_left = 0;
+    }
+
+    /// Slide the pitch over to the current note from `from` (a phase step, like `step`),
+    /// over the timbre's glide time
+    fn glide_from(&mut self, from: f32) {
+        let samples = (self.timbre.glide * SAMPLE_RATE as f32) as usize;
+        if samples == 0 || from <= 0.0 || from == self.target { return }
+        self.glide_mul = (self.target / from).powf(1.0 / samples as f32);
+        self.glide_left = samples;
+        self.step = from;
+    }
+
+    /// Mix this voice's next `out.len()` samples into `out`
+    fn render(&mut self, out: &mut [f32]) {
+        let t = self.timbre;
+        for o in out.iter_mut() {
+            let Some(env) = t.envelope.gain(self.elapsed as f32, SAMPLE_RATE as f32) else {
+                self.active = false;
+                return
+            };
+
+            let p = self.phase;
+            let mut val = 0.0;
+            if t.sine > 0.0 { val += t.sine * (TAU * p).sin() }
+            if t.triangle > 0.0 { val += t.triangle * (4.0 * (p - (p + 0.5).floor()).abs() - 1.0) }
+            if t.square > 0.0 { val += t.square * if p < 0.5 { 1.0 } else { -1.0 } }
+            if t.sawtooth > 0.0 { val += t.sawtooth * 2.0 * (p - (p + 0.5).floor()) }
+            if t.noise > 0.0 {
+                // Xorshift, scaled to -1.0 .. 1.0
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                val += t.noise * (self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            }
+            self.phase = (p + self.step).fract();
+            if self.glide_left > 0 {
+                self.glide_left -= 1;
+                self.step = if self.glide_left == 0 { self.target } else { self.step * self.glide_mul };
+            }
+
+            val *= env * self.gain;
+            if self.stolen {
+                if self.fade == 0 {
+                    self.active = false;
+                    return
+                }
+                val *= self.fade as f32 / STEAL_FADE as f32;
+                self.fade -= 1;
+            }
+
+            self.meter.update(val);
+            self.elapsed += 1;
+            *o += val;
+        }
+    }
+}
+
+/// Turn a volume (0.0 .. 1.0, louder is clamped) into an amplitude, on the same curve as
+/// rodio's `amplify_normalized`
+fn gain(volume: f32) -> f32 {
+    let volume = volume.clamp(0.0, 1.0);
+    let amplitude = (6.907_755_4 * volume).exp() / 1000.0;
+    if volume < 0.1 { amplitude * volume * 10.0 } else { amplitude }
+}
+
+/// What the UI thread sends the synth
+enum Event {
+    Note(Note),
+
+    /// Fade out everything that's sounding
+    Silence
+}
+
+/// The synthesizer: a fixed pool of voices, rendered a block at a time into a single source
+/// that lives on the output mixer for the whole run of the program. Notes are sent to it
+/// through a `SynthHandle`.
+pub struct Synth {
+    voices: Vec<PoolVoice>,
+    events: Receiver<Event>,
+    max_voices: Arc<AtomicUsize>,
+    buffer: [f32; BLOCK],
+    pos: usize,
+
+    // Increases with every note so we can tell which voice is oldest
+    serial: u64
+}
+
+/// The other end of a `Synth`, for sending it notes from the UI thread
+#[derive(Clone)]
+pub struct SynthHandle {
+    sender: Sender<Event>,
+    max_voices: Arc<AtomicUsize>
+}
+
+impl SynthHandle {
+    pub fn play(&self, note: Note) {
+        // The only way this fails is if the output stream is gone, and then there's nobody to hear it
+        let _ = self.sender.send(Event::Note(note));
+    }
+
+    /// Stop every note, like when the song's thrown away
+    pub fn silence(&self) {
+        let _ = self.sender.send(Event::Silence);
+    }
+
+    /// The most voices that can sound at once, across all tracks
+    pub fn max_voices(&self) -> usize {
+        self.max_voices.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_voices(&self, max_voices: usize) {
+        self.max_voices.store(max_voices.clamp(*MAX_VOICES.start(), *MAX_VOICES.end()), Ordering::Relaxed)
+    }
+}
+
+impl Synth {
+    pub fn new() -> (Self, SynthHandle) {
+        let (sender, events) = channel();
+        let max_voices = Arc::new(AtomicUsize::new(DEFAULT_MAX_VOICES));
+        let synth = Self {
+            voices: vec![PoolVoice::default(); POOL_SIZE],
+            events,
+            max_voices: max_voices.clone(),
+            buffer: [0.0; BLOCK],
+            pos: BLOCK,
+            serial: 0
+        };
+        (synth, SynthHandle { sender, max_voices })
+    }
+
+    /// How many voices are sounding (not counting ones that are fading out after being stolen)
+    pub fn sounding(&self, track: Option<Id>) -> usize {
+        self.voices.iter()
+            .filter(|v| track.is_none_or(|t| t == v.track) && v.sounding())
+            .count()
+    }
+
+    /// Start a note, stealing older voices to stay under both its track's limit and the
+    /// global one.
+    pub fn note_on(&mut self, note: Note) {
+        self.serial += 1;
+        let track = note.track;
+
+        match note.voices.mode {
+            VoiceMode::Poly => {
+                while self.sounding(Some(track)) >= note.voices.polyphony.max(1) {
+                    self.steal(Some(track))
+                }
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                // Where the track's last note left its pitch, if we're going to glide from it.
+                // Voices keep their settings after they finish, so this works for notes that
+                // have ended too, as long as the voice hasn't been reused.
+                let previous = self.voices.iter()
+                    .filter(|v| v.track == track && v.serial > 0)
+                    .max_by_key(|v| v.serial);
+                let glide_from = match note.timbre.glide_mode {
+                    GlideMode::Always => previous.map(|v| v.step),
+                    GlideMode::Tied => previous.filter(|v| v.sounding()).map(|v| v.step)
+                };
+
+                let newest = self.voices.iter()
+                    .enumerate()
+                    .filter(|(_, v)| v.track == track && v.sounding())
+                    .max_by_key(|(_, v)| v.serial)
+                    .map(|(i, _)| i);
+
+                for (i, v) in self.voices.iter_mut().enumerate() {
+                    if v.track == track && v.sounding() && (note.voices.mode == VoiceMode::Mono || Some(i) != newest) {
+                        v.stolen = true
+                    }
+                }
+
+                // In legato, slide the voice that was already going over to the new note,
+                // and pick its envelope up from the end of the decay
+                if note.voices.mode == VoiceMode::Legato && let Some(i) = newest {
+                    let v = &mut self.voices[i];
+                    v.elapsed = v.elapsed.min(note.timbre.envelope.hold_start(SAMPLE_RATE as f32));
+                    v.serial = self.serial;
+                    v.retune(&note);
+                    if let Some(from) = glide_from { v.glide_from(from) }
+                    return
+                }
+
+                let Some(slot) = self.free_voice() else { return };
+                self.voices[slot].start(&note, self.serial);
+                if let Some(from) = glide_from { self.voices[slot].glide_from(from) }
+                return
+            }
+        }
+
+        let Some(slot) = self.free_voice() else { return };
+        self.voices[slot].start(&note, self.serial)
+    }
+
+    /// Stay under the global voice limit, and find a voice for a new note: a free one if
+    /// there is one, otherwise whichever stolen voice is closest to done fading. None only
+    /// if the pool is empty.
+    fn free_voice(&mut self) -> Option<usize> {
+        while self.sounding(None) >= self.max_voices.load(Ordering::Relaxed).clamp(1, POOL_SIZE) {
+            self.steal(None)
+        }
+
+        self.voices.iter()
+            .enumerate()
+            .min_by_key(|(_, v)| (v.active, v.fade))
+            .map(|(i, _)| i)
+    }
+
+    /// Pick a voice (from one track, or all of them) and make it fade out, the same way
+    /// `VoiceAllocator` does
+    fn steal(&mut self, track: Option<Id>) {
+        let candidates = self.voices.iter_mut()
+            .filter(|v| track.is_none_or(|t| t == v.track) && v.sounding());
+
+        if let Some(v) = victim(candidates, |v| (v.level(), v.serial)) {
+            v.stolen = true
+        }
+    }
+
+    /// Start any notes we've been sent, then render the next block of samples into `out`
+    pub fn render_block(&mut self, out: &mut [f32]) {
+        self.receive();
+        Self::mix(&mut self.voices, out)
+    }
+
+    fn receive(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Event::Note(note) => self.note_on(note),
+                Event::Silence => self.voices.iter_mut().for_each(|v| v.stolen = true)
+            }
+        }
+    }
+
+    fn mix(voices: &mut [PoolVoice], out: &mut [f32]) {
+        out.fill(0.0);
+        for v in voices.iter_mut().filter(|v| v.active) {
+            v.render(out)
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == BLOCK {
+            self.receive();
+            Self::mix(&mut self.voices, &mut self.buffer);
+            self.pos = 0;
+        }
+        self.pos += 1;
+        Some(self.buffer[self.pos - 1])
+    }
+}
+
+impl Source for Synth {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        1
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None // The synth plays until the program ends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use crate::voice::Voices;
+
+    fn note(track: &str, tone: i32, voices: Voices) -> Note {
+        Note {
+            tone,
+            volume: 1.0,
+            timbre: Timbre {
+                envelope: Envelope { attack: 0.0, decay: 0.0, sustain: 1.0, hold: 1.0, release: 0.0 },
+                ..Timbre::default()
+            },
+            track: Id::new(track),
+            voices
+        }
+    }
+
+    fn poly(polyphony: usize) -> Voices {
+        Voices { mode: VoiceMode::Poly, polyphony }
+    }
+
+    #[test]
+    fn test_limits() {
+        let (mut synth, handle) = Synth::new();
+        handle.set_max_voices(6);
+        for n in 0..10 {
+            synth.note_on(note("a", n, poly(4)));
+        }
+        assert_eq!(synth.sounding(Some(Id::new("a"))), 4);
+
+        for n in 0..10 {
+            synth.note_on(note("b", n, poly(16)));
+        }
+        assert_eq!(synth.sounding(None), 6);
+        assert_eq!(synth.voices.len(), POOL_SIZE);
+    }
+
+    #[test]
+    fn test_pool_never_grows() {
+        let (mut synth, handle) = Synth::new();
+        handle.set_max_voices(POOL_SIZE);
+        let mut out = [0.0; BLOCK];
+        for n in 0..1000 {
+            synth.note_on(note("a", n % 16, poly(usize::MAX)));
+            if n % 100 == 0 { synth.render_block(&mut out) }
+        }
+        assert_eq!(synth.voices.len(), POOL_SIZE);
+        assert_eq!(synth.voices.capacity(), POOL_SIZE);
+        assert!(synth.sounding(None) <= POOL_SIZE);
+    }
+
+    #[test]
+    fn test_legato_keeps_voice() {
+        let (mut synth, _handle) = Synth::new();
+        let legato = Voices { mode: VoiceMode::Legato, polyphony: 1 };
+        synth.note_on(note("a", 0, legato));
+        let mut out = [0.0; BLOCK];
+        synth.render_block(&mut out);
+
+        synth.note_on(note("a", 12, legato));
+        assert_eq!(synth.sounding(None), 1);
+        let v = synth.voices.iter().find(|v| v.sounding()).unwrap();
+        assert_eq!(v.elapsed, 0); // Clamped to the end of the (empty) decay
+        assert!((v.step - freq(12) / SAMPLE_RATE as f32).abs() < 0.0001);
+
+        // Mono cuts the old one off and starts fresh
+        let mono = Voices { mode: VoiceMode::Mono, polyphony: 1 };
+        synth.note_on(note("a", 3, mono));
+        assert_eq!(synth.sounding(None), 1);
+        assert_eq!(synth.voices.iter().filter(|v| v.active).count(), 2);
+    }
+
+    #[test]
+    fn test_glide() {
+        let (mut synth, _handle) = Synth::new();
+        let mono = Voices { mode: VoiceMode::Mono, polyphony: 1 };
+        let gliding = |tone, glide_mode| {
+            let mut n = note("a", tone, mono);
+            n.timbre.glide = 0.1;
+            n.timbre.glide_mode = glide_mode;
+            n
+        };
+
+        synth.note_on(gliding(0, GlideMode::Always));
+        synth.note_on(gliding(12, GlideMode::Always));
+        let v = *synth.voices.iter().find(|v| v.sounding()).unwrap();
+        assert!((v.step - freq(0) / SAMPLE_RATE as f32).abs() < 0.0001);
+
+        // A tenth of a second later it's arrived
+        let mut out = vec![0.0; SAMPLE_RATE as usize / 10];
+        synth.render_block(&mut out);
+        let v = *synth.voices.iter().find(|v| v.sounding()).unwrap();
+        assert_eq!(v.step, freq(12) / SAMPLE_RATE as f32);
+
+        // Once the note has ended, only "always" glides from it
+        let mut out = vec![0.0; SAMPLE_RATE as usize];
+        synth.render_block(&mut out);
+        synth.note_on(gliding(5, GlideMode::Tied));
+        let v = *synth.voices.iter().find(|v| v.sounding()).unwrap();
+        assert_eq!(v.step, freq(5) / SAMPLE_RATE as f32);
+
+        synth.render_block(&mut out);
+        synth.note_on(gliding(0, GlideMode::Always));
+        let v = *synth.voices.iter().find(|v| v.sounding()).unwrap();
+        assert!((v.step - freq(5) / SAMPLE_RATE as f32).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_voices_finish() {
+        let (mut synth, _handle) = Synth::new();
+        synth.note_on(note("a", 0, poly(8)));
+        let mut out = [0.0; BLOCK];
+        synth.render_block(&mut out);
+        assert!(out.iter().any(|s| *s != 0.0));
+
+        // One second of hold and no release, so the voice is done after a second
+        for _ in 0..(SAMPLE_RATE as usize / BLOCK) {
+            synth.render_block(&mut out)
+        }
+        assert_eq!(synth.voices.iter().filter(|v| v.active).count(), 0);
+    }
+}
@@ -0,0 +1,181 @@
+use eframe::egui;
+use eframe::egui::{Context, Window};
+use serde::{Deserialize, Serialize};
+use crate::gui::Showable;
+use crate::tenori::{LOOP_LENGTH, MAX_TEMPO, MIN_TEMPO};
+
+/// How far along the lane a point is measured in
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum LaneUnit {
+    /// Times through the loop
+    #[default]
+    Loop,
+    Bar
+}
+
+/// How the tempo gets to a point's tempo from the one before it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TempoChange {
+    /// Jump straight there when we reach the point
+    #[default]
+    Step,
+    /// Slide there evenly from the point before
+    Ramp
+}
+
+impl TempoChange {
+    pub fn label(self) -> &'static str {
+        match self {
+            TempoChange::Step => "Step",
+            TempoChange::Ramp => "Ramp"
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TempoPoint {
+    /// Which loop or bar (counting from 0 at the start) this tempo is reached at
+    pub at: u32,
+
+    /// In beats per minute
+    pub tempo: f32,
+
+    pub change: TempoChange
+}
+
+/// Changes of tempo over time, counted from when we started playing from the top. Before
+/// the first point we play at the normal tempo, and after the last one we stay at its tempo.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TempoLane {
+    pub enabled: bool,
+    pub unit: LaneUnit,
+
+    /// Kept in order of `at`
+    pub points: Vec<TempoPoint>,
+
+    /// Whether the tempo lane window is showing
+    #[serde(skip)]
+    pub open: bool
+}
+
+impl TempoLane {
+    /// How far along the lane we are (in loops or bars) after playing some number of steps
+    pub fn position(&self, steps: f32, steps_per_bar: f32) -> f32 {
+        match self.unit {
+            LaneUnit::Loop => steps / LOOP_LENGTH as f32,
+            LaneUnit::Bar => steps / steps_per_bar.max(1.0)
+        }
+    }
+
+    /// The tempo at a position along the lane, given the tempo to use before the first point
+    pub fn tempo_at(&self, base: f32, position: f32) -> f32 {
+        if !self.enabled { return base }
+
+        let next = self.points.iter().position(|p| p.at as f32 > position);
+        let prev = match next {
+            Some(0) => None,
+            Some(n) => Some(self.points[n - 1]),
+            None => self.points.last().copied()
+        };
+        let (from_at, from_tempo) = prev.map(|p| (p.at as f32, p.tempo)).unwrap_or((0.0, base));
+
+        match next.map(|n| self.points[n]) {
+            Some(to) if to.change == TempoChange::Ramp => {
+                let length = to.at as f32 - from_at;
+                let through = if length > 0.0 { (position - from_at) / length } else { 1.0 };
+                from_tempo + (to.tempo - from_tempo) * through.clamp(0.0, 1.0)
+            }
+            _ => from_tempo
+        }
+    }
+}
+
+impl Showable<()> for TempoLane {
+    fn show(&mut self, ctx: &Context, _state: &()) {
+        let mut open = true;
+        let window = Window::new("Tempo")
+            .open(&mut open)
+            .resizable([false, false]);
+
+        window.show(ctx, |ui| {
+            ui.checkbox(&mut self.enabled, "Follow the tempo lane");
+            ui.horizontal(|ui| {
+                ui.label("Count in");
+                ui.radio_value(&mut self.unit, LaneUnit::Loop, "Loops");
+                ui.radio_value(&mut self.unit, LaneUnit::Bar, "Bars");
+            });
+
+            let mut remove = None;
+            let mut moved = false;
+            egui::Grid::new("tempo lane").striped(true).show(ui, |ui| {
+                ui.label("At");
+                ui.label("Tempo");
+                ui.label("Change");
+                ui.end_row();
+
+                for (i, point) in self.points.iter_mut().enumerate() {
+                    // Shown counting from 1, like the bar.beat.step display
+                    let mut at = point.at + 1;
+                    if ui.add(egui::DragValue::new(&mut at).range(1..=9999)).changed() {
+                        point.at = at - 1;
+                        moved = true
+                    }
+                    ui.add(egui::DragValue::new(&mut point.tempo).range(MIN_TEMPO..=MAX_TEMPO).speed(0.5));
+                    egui::ComboBox::from_id_salt(("tempo change", i))
+                        .selected_text(point.change.label())
+                        .show_ui(ui, |ui| {
+                            for change in [TempoChange::Step, TempoChange::Ramp] {
+                                ui.selectable_value(&mut point.change, change, change.label());
+                            }
+                        });
+                    if ui.button("x").clicked() { remove = Some(i) }
+                    ui.end_row();
+                }
+            });
+
+            if let Some(i) = remove { self.points.remove(i); }
+            if moved { self.points.sort_by_key(|p| p.at) }
+
+            if ui.button("Add").clicked() {
+                let point = self.points.last().map(|p| TempoPoint { at: p.at + 1, ..*p })
+                    .unwrap_or(TempoPoint { at: 1, tempo: 120.0, change: TempoChange::Step });
+                self.points.push(point)
+            }
+        });
+
+        self.open = open;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lane(points: &[(u32, f32, TempoChange)]) -> TempoLane {
+        TempoLane {
+            enabled: true,
+            unit: LaneUnit::Loop,
+            points: points.iter().map(|&(at, tempo, change)| TempoPoint { at, tempo, change }).collect(),
+            open: false
+        }
+    }
+
+    #[test]
+    fn test_tempo_at() {
+        let changes = lane(&[(2, 120.0, TempoChange::Step), (4, 60.0, TempoChange::Ramp)]);
+        assert_eq!(changes.tempo_at(90.0, 0.0), 90.0);
+        assert_eq!(changes.tempo_at(90.0, 1.9), 90.0);
+        assert_eq!(changes.tempo_at(90.0, 2.0), 120.0);
+        assert_eq!(changes.tempo_at(90.0, 3.0), 90.0);
+        assert_eq!(changes.tempo_at(90.0, 4.0), 60.0);
+        assert_eq!(changes.tempo_at(90.0, 10.0), 60.0);
+
+        // A ramp to the first point starts from the normal tempo
+        let ramp = lane(&[(2, 120.0, TempoChange::Ramp)]);
+        assert_eq!(ramp.tempo_at(100.0, 1.0), 110.0);
+
+        let off = TempoLane { enabled: false, ..ramp };
+        assert_eq!(off.tempo_at(100.0, 1.0), 100.0);
+    }
+}
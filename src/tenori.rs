@@ -1,113 +1,903 @@
-use std::time::Instant;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use eframe::egui::Id;
 use rodio::OutputStream;
+use serde::{Deserialize, Serialize};
 use crate::grid::Grid;
+use crate::history::History;
+use crate::clip::Clip;
 use crate::dialog::Dialog;
+use crate::record::{nearest_row, RecordMode};
 use crate::noise::Note;
+use crate::prefs::Prefs;
+use crate::saveload::PersistedTenori;
+use crate::scene::{Scene, SceneTrack, Scenes};
+use crate::song::Song;
+use crate::synth::{Synth, SynthHandle};
+use crate::tempo::TempoLane;
 
 pub const LOOP_LENGTH: u32 = 16;
 
+/// How many steps to a beat, unless the user changes it (sixteenth notes)
+pub const DEFAULT_STEPS_PER_BEAT: u32 = 4;
+
+/// The tempo of a new song, in beats per minute
+pub const DEFAULT_TEMPO: f32 = 90.0;
+
+/// The range of tempos, in beats per minute
+pub const MIN_TEMPO: f32 = 20.0;
+pub const MAX_TEMPO: f32 = 300.0;
+
+/// Taps further apart than this start a new tap tempo, rather than slowing down the old one
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many of the latest taps to average the tempo over
+const TAPS: usize = 8;
+
+/// The most steps the timer moves in one go before looking at the tempo lane again
+const MAX_ADVANCE: f32 = 0.25;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeSignature {
+    /// Beats in a bar
+    pub beats: u32,
+
+    /// Which note gets the beat: 4 for quarter notes, 8 for eighths, etc
+    pub unit: u32
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, unit: 4 }
+    }
+}
+
+/// A stretch of the loop to play over and over instead of the whole thing
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoopRegion {
+    pub enabled: bool,
+
+    /// The first and last steps to play, counting from 0
+    pub first: u32,
+    pub last: u32
+}
+
+impl Default for LoopRegion {
+    fn default() -> Self {
+        Self { enabled: false, first: 0, last: LOOP_LENGTH - 1 }
+    }
+}
+
+/// Where we are in the loop, in musical terms. All of these count from 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Position {
+    pub bar: u32,
+    pub beat: u32,
+    pub step: u32
+}
+
+/// A track taken out of the grids, along with its parts of the song and scenes, so that it
+/// can be put back as it was
+#[derive(Clone)]
+pub struct SavedTrack {
+    pub grid: Grid,
+    pub song: Vec<Option<usize>>,
+    pub scenes: Vec<Option<SceneTrack>>
+}
+
 pub struct Tenori {
     /// Tempo in beats per minute
-    pub tempo: u32,
+    pub tempo: f32,
+
+    // When the tap tempo button was last pressed, most recent last
+    taps: Vec<Instant>,
 
-    // A count _in beats_ of where we are in the loop
+    /// How many steps (columns of the grid) there are to a beat
+    pub steps_per_beat: u32,
+
+    pub time_signature: TimeSignature,
+
+    // A count _in steps_ of where we are in the loop. Negative while we're counting in.
     pub timer: f32,
 
+    /// How many times we've gone around the loop since we started from the top
+    pub loops: u32,
+
+    /// Which part of the loop we're going around
+    pub loop_region: LoopRegion,
+
+    /// Changes of tempo as we go around the loop
+    pub tempo_lane: TempoLane,
+
     /// Whether or not we're playing; false == paused
     pub playing: bool,
 
+    /// How far (as a fraction of a step, 0.0 .. 0.5) every other step is pushed back
+    pub swing: f32,
+
     // The instant of the last time we called tick()
     last_tick: Option<Instant>,
 
+    // The stretch of the loop the timer last moved through, as returned by advance()
+    passed: (f32, f32),
+
+    // Cells the keyboard has recorded and already sounded, and when in the loop they play,
+    // so they aren't played again when the playhead gets to them
+    sounded: Vec<(Id, usize, f32)>,
+
     /// The grids that we currently have going
     pub grids: Vec<Grid>,
 
+    /// The grid the keyboard plays, if it's still there; otherwise the first one
+    pub selected: Option<Id>,
+
+    /// Whether a note's been recorded since we started playing with Rec on. Replace only
+    /// clears columns from then on, so playing along without recording wipes nothing.
+    pub punched_in: bool,
+
+    /// The cells we copied last, from any grid
+    pub clipboard: Option<Clip>,
+
+    /// The arrangement of the grids' patterns into a song
+    pub song: Song,
+
+    /// Snapshots of the grids that can be launched live
+    pub scenes: Scenes,
+
+    /// Edits to undo and redo
+    pub history: History,
+
     /// Running count of windows created (for ids)
     pub window_counter: usize,
 
-    // The audio output stream to which we will play notes
-    output_stream: OutputStream,
+    // The audio output stream the synth plays through. We never touch it again, but
+    // dropping it would stop the sound. There isn't one when we're running headless.
+    _output_stream: Option<OutputStream>,
+
+    /// Where we send notes to be played
+    pub synth: SynthHandle,
 
-    /// Error dialogs we're currently showing
+    /// Dialogs we're currently showing
     pub dialogs: Vec<Dialog>,
 
+    /// Whether the user has changed anything since we last saved or loaded
+    pub dirty: bool,
+
+    /// Set once the user has said it's okay to quit
+    pub quitting: bool,
+
+    /// Settings for the program rather than the song
+    pub prefs: Prefs,
+
+    /// When we last wrote the recovery file, and what we wrote
+    pub autosaved: (Instant, String),
+
     /// If present, we can save to this file without asking the
-    /// user to select a file first.
-    pub default_filename: Option<String>,
+    /// user to select a file first. It's the file we last loaded or saved.
+    pub default_filename: Option<PathBuf>,
 }
 
 impl Default for Tenori {
     fn default() -> Self {
         let output_stream = rodio::OutputStreamBuilder::open_default_stream()
             .expect("Open audio output stream");
+        let (synth_source, synth) = Synth::new();
+        output_stream.mixer().add(synth_source);
+        Self::with_synth(synth, Some(output_stream))
+    }
+}
+
+impl Tenori {
+    /// A Tenori without an audio device, for rendering offline. Its notes go to the synth
+    /// that comes with it, for the caller to render.
+    pub fn headless() -> (Self, Synth) {
+        let (synth, handle) = Synth::new();
+        (Self::with_synth(handle, None), synth)
+    }
 
-        Self {
-            tempo: 90,
+    fn with_synth(synth: SynthHandle, output_stream: Option<OutputStream>) -> Self {
+        let mut tenori = Self {
+            tempo: DEFAULT_TEMPO,
+            taps: vec![],
+            steps_per_beat: DEFAULT_STEPS_PER_BEAT,
+            time_signature: TimeSignature::default(),
             timer: 0.0,
-            playing: true,
+            loops: 0,
+            loop_region: LoopRegion::default(),
+            tempo_lane: TempoLane::default(),
+            playing: false,
+            swing: 0.0,
             last_tick: None,
+            passed: (0.0, 0.0),
+            sounded: vec![],
             grids: vec![],
+            selected: None,
+            punched_in: false,
+            clipboard: None,
+            song: Song::default(),
+            scenes: Scenes::default(),
+            history: History::default(),
             window_counter: 0,
             dialogs: vec![],
+            dirty: false,
+            quitting: false,
+            prefs: Prefs::default(),
+            autosaved: (Instant::now(), String::new()),
             default_filename: None,
-            output_stream
-        }
+            synth,
+            _output_stream: output_stream
+        };
+        tenori.new_song();
+        tenori.mark_clean();
+        tenori
     }
-}
 
-impl Tenori {
     /// Call this every frame to update the timer / last tick based on the current instant
-    /// and the tempo
-    pub fn tick(&mut self) -> bool {
+    /// and the tempo. Returns the stretch of the loop (in steps) that we moved through, from
+    /// the old timer to the new one; if we wrapped around the end of the loop then the
+    /// second one will be less than the first.
+    pub fn tick(&mut self) -> (f32, f32) {
         let now = Instant::now();
-        let old_beat = self.beat();
-        if let Some(last) = self.last_tick && self.playing {
-            let dt = (now - last).as_secs_f32();
-            let bps = (self.tempo as f32) / 60.0;
-            // Timer is an amount of time _in beats_ and some of those beats might have been for a
-            // different tempo.
-            // We know now a time delta in seconds and a conversion factor to turn that to beats, so:
-            self.timer += dt * bps;
-            // 16 beats in the loop, so timer should never be over 16:
-            while self.timer > LOOP_LENGTH as f32 { self.timer -= LOOP_LENGTH as f32 }
-        }
+        let dt = match self.last_tick {
+            Some(last) if self.playing => (now - last).as_secs_f32(),
+            _ => 0.0
+        };
 
         // Playing or not, update last_tick so that the next frame adds the correct duration to timer
         self.last_tick = Some(now);
 
-        // Did we enter a new beat?
-        self.beat() != old_beat
+        self.advance(dt)
+    }
+
+    /// Move the timer along by `dt` seconds of playing, following the tempo lane. Returns the
+    /// stretch of the loop we moved through, like `tick`.
+    pub fn advance(&mut self, dt: f32) -> (f32, f32) {
+        let old_timer = self.timer;
+        let (start, end) = self.loop_bounds();
+        let passed = self.passed;
+        self.sounded.retain(|(_, _, t)| !crossed(passed, *t));
+        let mut left = dt;
+        while left > 0.0 {
+            // Timer is an amount of time _in steps_ and some of those steps might have been for a
+            // different tempo. The tempo lane can change it as we go, so take small bites.
+            let steps_per_sec = self.current_tempo() * self.steps_per_beat.max(1) as f32 / 60.0;
+            let steps = (left * steps_per_sec).min(MAX_ADVANCE);
+            self.timer += steps;
+            left = if steps < MAX_ADVANCE { 0.0 } else { left - steps / steps_per_sec };
+
+            // 16 steps in the loop (unless we're looping a region), so timer should never be over 16:
+            while self.timer > end {
+                self.timer -= end - start;
+                if !self.end_of_loop() {
+                    self.passed = (old_timer, old_timer);
+                    return self.passed
+                }
+            }
+        }
+
+        self.passed = (old_timer, self.timer);
+        self.passed
+    }
+
+    /// Start playing. From the top of the loop, that's after the metronome's count-in.
+    pub fn start(&mut self) {
+        let (start, _) = self.loop_bounds();
+        if self.timer == start {
+            self.timer = start - (self.prefs.metronome.count_in * self.steps_per_bar()) as f32
+        }
+        self.playing = true
+    }
+
+    /// Stop playing and go back to the top
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.rewind()
+    }
+
+    /// Go back to the top of the loop (or loop region), and the start of the song
+    pub fn rewind(&mut self) {
+        self.timer = self.loop_bounds().0;
+        self.sounded.clear();
+        self.loops = 0;
+        self.song.rewind();
+        self.apply_song()
+    }
+
+    /// Move the timer to a step, keeping it inside the loop region
+    pub fn seek(&mut self, step: u32) {
+        let region = self.loop_region;
+        self.sounded.clear();
+        self.timer = if region.enabled { step.clamp(region.first, region.last) } else { step % LOOP_LENGTH } as f32
+    }
+
+    /// While paused, move one step forwards or backwards and play the column we land on
+    pub fn step_by(&mut self, forward: bool) {
+        self.playing = false;
+        let (first, last) = self.loop_steps();
+        let current = self.timer.max(0.0).floor() as u32;
+        let step = if forward {
+            if current >= last { first } else { current + 1 }
+        } else if self.timer > current as f32 {
+            // Partway through a step goes back to its start
+            current
+        } else if current <= first {
+            last
+        } else {
+            current - 1
+        };
+        self.timer = step as f32;
+        self.sounded.clear();
+
+        for note in self.notes_at_step(step) {
+            self.play(note)
+        }
+    }
+
+    /// The first and last steps we're looping over
+    pub fn loop_steps(&self) -> (u32, u32) {
+        let region = self.loop_region;
+        if region.enabled {
+            (region.first.min(LOOP_LENGTH - 1), region.last.clamp(region.first, LOOP_LENGTH - 1))
+        } else {
+            (0, LOOP_LENGTH - 1)
+        }
+    }
+
+    /// Where the timer goes round from and to, in steps
+    pub fn loop_bounds(&self) -> (f32, f32) {
+        let (first, last) = self.loop_steps();
+        (first as f32, (last + 1) as f32)
+    }
+
+    /// Keep the timer inside the loop region after it changes
+    pub fn clamp_to_region(&mut self) {
+        let (start, end) = self.loop_bounds();
+        if self.timer >= 0.0 && (self.timer < start || self.timer > end) {
+            self.timer = start
+        }
+    }
+
+    /// How many steps there are in a bar
+    pub fn steps_per_bar(&self) -> u32 {
+        self.steps_per_beat.max(1) * self.time_signature.beats.max(1)
+    }
+
+    /// Set the tempo from how fast the tap tempo button is being pressed
+    pub fn tap(&mut self) {
+        let now = Instant::now();
+        if self.taps.last().is_some_and(|last| now - *last > TAP_TIMEOUT) {
+            self.taps.clear()
+        }
+        self.taps.push(now);
+        if self.taps.len() > TAPS { self.taps.remove(0); }
+
+        if let (Some(first), Some(last)) = (self.taps.first(), self.taps.last()) && self.taps.len() > 1 {
+            let beat = (*last - *first).as_secs_f32() / (self.taps.len() - 1) as f32;
+            self.set_tempo(60.0 / beat)
+        }
     }
 
-    /// Which beat (0..loop_length) we're on
-    pub fn beat(&self) -> u32 {
+    /// Change the tempo, keeping it in range and to two decimal places
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.tempo = ((tempo * 100.0).round() / 100.0).clamp(MIN_TEMPO, MAX_TEMPO)
+    }
+
+    /// The tempo we're playing at right now, in beats per minute
+    pub fn current_tempo(&self) -> f32 {
+        let steps = (self.loops * LOOP_LENGTH) as f32 + self.timer;
+        self.tempo_lane.tempo_at(self.tempo, self.tempo_lane.position(steps, self.steps_per_bar() as f32))
+    }
+
+    /// Called every time we go around the loop. Returns false if we should stop playing.
+    fn end_of_loop(&mut self) -> bool {
+        self.loops += 1;
+        let mut more = true;
+        if self.song.enabled {
+            more = self.song.advance();
+            self.apply_song();
+        }
+
+        // Anything queued up live goes in on top of the song
+        for grid in self.grids.iter_mut() {
+            if let Some(pattern) = grid.queued.take() {
+                grid.select_pattern(pattern)
+            }
+        }
+        if let Some(scene) = self.scenes.queued.take() {
+            self.apply_scene(scene)
+        }
+
+        if !more {
+            // That was the end of the song, so stop at the start of it
+            self.playing = false;
+            self.timer = 0.0;
+            self.loops = 0;
+        }
+        more
+    }
+
+    /// Save a snapshot of every grid as a new scene
+    pub fn capture_scene(&mut self) {
+        let tracks = self.grids.iter().map(|g| SceneTrack {
+            pattern: g.get_pattern(g.pattern),
+            volume: g.volume,
+            muted: g.muted
+        }).collect();
+        let name = format!("Scene {}", self.scenes.scenes.len() + 1);
+        self.scenes.scenes.push(Scene { name, tracks })
+    }
+
+    /// Launch a scene: right now if we're paused, otherwise at the end of the loop
+    pub fn launch_scene(&mut self, scene: usize) {
+        if self.playing {
+            self.scenes.queued = Some(scene)
+        } else {
+            self.apply_scene(scene)
+        }
+    }
+
+    /// Select a scene's notes in the grids (see `Grid::load_pattern`), and set their volumes
+    /// and mutes
+    fn apply_scene(&mut self, scene: usize) {
+        let Some(scene) = self.scenes.scenes.get(scene) else { return };
+        for (grid, track) in self.grids.iter_mut().zip(scene.tracks.iter()) {
+            grid.load_pattern(&track.pattern);
+            grid.volume = track.volume;
+            grid.muted = track.muted;
+        }
+    }
+
+    /// Throw away the song and start an empty one, the same as when we start up
+    pub fn new_song(&mut self) {
+        PersistedTenori::default().apply_to(self);
+        self.taps.clear();
+        self.last_tick = None;
+        self.sounded.clear();
+        self.punched_in = false;
+        self.selected = None;
+        self.clipboard = None;
+        self.default_filename = None;
+        self.synth.silence()
+    }
+
+    /// Take a track out. The song and scenes refer to tracks by position, so they lose
+    /// their parts for it too.
+    pub fn remove_track(&mut self, index: usize) -> Option<SavedTrack> {
+        if index >= self.grids.len() { return None }
+        Some(SavedTrack {
+            grid: self.grids.remove(index),
+            song: self.song.remove_track(index),
+            scenes: self.scenes.remove_track(index)
+        })
+    }
+
+    /// Put a track (back) in at a position
+    pub fn insert_track(&mut self, index: usize, track: SavedTrack) {
+        let index = index.min(self.grids.len());
+        let SavedTrack { mut grid, song, scenes } = track;
+        grid.open = true;
+        self.grids.insert(index, grid);
+        self.song.insert_track(index, &song);
+        self.scenes.insert_track(index, &scenes);
+    }
+
+    /// Select whatever patterns the song says the grids should be playing now, if we're
+    /// playing the song
+    pub fn apply_song(&mut self) {
+        if !self.song.enabled { return }
+        for (i, grid) in self.grids.iter_mut().enumerate() {
+            if let Some(pattern) = self.song.pattern(i) {
+                grid.select_pattern(pattern)
+            }
+        }
+    }
+
+    /// Which step (0..loop_length) we're on
+    pub fn step(&self) -> u32 {
         self.timer.floor() as u32
     }
 
+    /// Which bar, beat and step we're on
+    pub fn position(&self) -> Position {
+        position(self.step(), self.steps_per_beat, self.time_signature)
+    }
+
+    /// Play whatever the grids have asked to audition
+    pub fn play_auditions(&mut self) {
+        let notes: Vec<_> = self.grids.iter_mut()
+            .flat_map(|g| std::mem::take(&mut g.audition).into_iter().map(|tone| g.note(tone)).collect::<Vec<_>>())
+            .collect();
+        for note in notes {
+            self.play(note)
+        }
+    }
+
     /// What fraction we are (0.0..1.0) through the loop
     /// (multiply by window width to find the x coord to draw the cursor line)
     pub fn ratio(&self) -> f32 {
-        self.timer / LOOP_LENGTH as f32
+        self.timer.max(0.0) / LOOP_LENGTH as f32
+    }
+
+    /// All the notes that start in a stretch of the loop, as returned by `tick`: every
+    /// column we entered, and every arpeggio note for tracks that arpeggiate.
+    pub fn notes_between(&self, from: f32, to: f32) -> Vec<Note> {
+        self.notes_in(from, to, self.loops as i64)
     }
 
-    pub fn notes_for_beat(&self) -> Vec<Note> {
+    /// `notes_between`, for the stretch of loop number `loops`. The tick for the very end
+    /// of one loop is the start of the next one.
+    fn notes_in(&self, from: f32, to: f32, loops: i64) -> Vec<Note> {
+        let (start, end) = self.loop_bounds();
+        if to < from {
+            // We've just gone round, so the part up to the end was the loop before
+            let mut notes = self.notes_in(from, end, loops - 1);
+            notes.extend(self.notes_in(start, to, loops));
+            return notes
+        }
+
+        if from < start {
+            // Counting in. Nothing plays until we get to the start of the loop, which is
+            // also where the notes at the very end of it would have played.
+            if to < start { return vec![] }
+            let mut notes = self.notes_in(end - 0.001, end, loops - 1);
+            notes.extend(self.notes_in(start, to, loops));
+            return notes
+        }
+
+        let (first, last) = self.loop_steps();
+        let in_region = |step: u32| step >= first && step <= last;
+        let in_window = |t: f32| t > from && t <= to;
         let mut notes = vec![];
-        let beat = self.beat();
+        for grid in self.grids.iter().filter(|g| !g.muted) {
+            let swing = grid.swing.unwrap_or(self.swing);
+            let note = |tone| grid.note(tone);
+
+            if grid.arp.enabled {
+                // Arpeggios swing on their own ticks. A swung tick can be up to half a tick
+                // late, so start looking one tick early.
+                let per_step = grid.arp.rate.per_beat(self.time_signature.unit) / self.steps_per_beat.max(1) as f32;
+                let mut ticks: Vec<u32> = ticks_between(from - 1.0 / per_step, to, per_step).collect();
 
-        for grid in self.grids.iter() {
-            for tone in grid.notes(beat).into_iter() {
+                // The tick right at the start of the loop plays at the very end of it, so it
+                // needs looking for separately when we're looping a region
+                let top = if first == 0 { LOOP_LENGTH } else { first } as f32 * per_step;
+                if top.fract() == 0.0 { ticks.push(top as u32) }
+                ticks.sort();
+                ticks.dedup();
 
-                notes.push(Note {
-                    tone,
-                    volume: grid.volume,
-                    timbre: grid.timbre
-                })
+                for tick in ticks {
+                    let step = (tick as f32 / per_step).floor() as u32 % LOOP_LENGTH;
+                    let t = loop_time((tick as f32 + swing_offset(tick, swing)) / per_step, start, end);
+                    if in_region(step) && in_window(t) {
+                        // Counted from when we started, so the arpeggio carries on round the loop
+                        let played = loops as f32 * (end - start) + loop_time(tick as f32 / per_step, start, end) - start;
+                        let count = (played * per_step).round().max(0.0) as u32;
+                        notes.extend(grid.arp.tone(&grid.notes_as_played(step), count).map(note))
+                    }
+                }
+            } else {
+                let mut tones = vec![];
+                let sounded = |n: usize| self.sounded.iter().any(|(id, s, _)| *id == grid.id && *s == n);
+                for n in (0..grid.notes.len()).filter(|n| grid.notes[*n] && !sounded(*n)) {
+                    let step = n as u32 % LOOP_LENGTH;
+                    if in_region(step) && in_window(loop_time(grid.cell_time(n, swing), start, end)) {
+                        tones.extend(grid.row_tones(Grid::row(n)))
+                    }
+                }
+                tones.sort();
+                tones.dedup();
+                notes.extend(tones.into_iter().map(note))
             }
         }
         notes
     }
 
+    /// The grid the keyboard plays
+    pub fn selected_grid(&mut self) -> Option<&mut Grid> {
+        let n = self.grids.iter().position(|g| Some(g.id) == self.selected).unwrap_or(0);
+        self.grids.get_mut(n)
+    }
+
+    /// Play a tone from the keyboard on the selected grid. If we're playing, it's written
+    /// into the grid too, at the nearest step.
+    pub fn record(&mut self, tone: i32) {
+        let (first, _) = self.loop_steps();
+        let (start, end) = self.loop_bounds();
+        let writing = self.playing && self.timer >= start;
+        let (step, nudge) = self.prefs.recorder.quantize(self.timer);
+        let (from, to) = self.passed;
+        let swing = self.swing;
+        let punch_in = writing && self.prefs.recorder.mode == RecordMode::Replace && !self.punched_in;
+        if writing { self.punched_in = true }
+        let Some(grid) = self.selected_grid() else { return };
+
+        let note = grid.note(tone);
+
+        if writing {
+            // The end of the loop is its first step again
+            let column = if step as f32 >= end { first } else { step };
+            let row = nearest_row(grid.scale, tone);
+            let n = ((LOOP_LENGTH - row - 1) * LOOP_LENGTH + column) as usize;
+            // The playhead's already past where this column would have been cleared
+            if punch_in { grid.clear_column(column) }
+            if !grid.notes[n] { grid.toggle(n) }
+            grid.nudges[n] = nudge;
+
+            // We're playing it now, so if the playhead hasn't got to the cell yet (it was
+            // pulled forward, or it's in what this frame moved through), skip it this time
+            let t = loop_time(grid.cell_time(n, grid.swing.unwrap_or(swing)), start, end);
+            let length = end - start;
+            let ahead = (t - from).rem_euclid(length);
+            if ahead > 0.0 && ahead <= (to - from).rem_euclid(length) + 1.0 {
+                let id = grid.id;
+                self.sounded.push((id, n, t))
+            }
+        }
+        self.play(note)
+    }
+
+    /// When recording over what's there, empty each column of the selected grid as the
+    /// playhead gets close enough that new notes would be quantized onto it. That starts
+    /// with the first note recorded, and stops when Rec is turned off or we stop playing.
+    pub fn clear_for_recording(&mut self, from: f32, to: f32) {
+        if !self.prefs.recorder.enabled || !self.playing {
+            self.punched_in = false;
+            return
+        }
+        if self.prefs.recorder.mode != RecordMode::Replace || !self.punched_in { return }
+        let (first, last) = self.loop_steps();
+        let (start, end) = self.loop_bounds();
+        if from < start { return }
+
+        let columns: Vec<u32> = (first..=last)
+            .filter(|c| crossed((from, to), if *c == first { end - 0.5 } else { *c as f32 - 0.5 }))
+            .collect();
+        let Some(grid) = self.selected_grid() else { return };
+        for column in columns {
+            grid.clear_column(column)
+        }
+    }
+
+    /// Every lit note in a column, all at once, for hearing what's there while paused
+    pub fn notes_at_step(&self, step: u32) -> Vec<Note> {
+        self.grids.iter().filter(|g| !g.muted).flat_map(|grid| {
+            grid.notes(step).into_iter().map(|tone| grid.note(tone))
+        }).collect()
+    }
+
+    /// The metronome clicks in a stretch of the loop, as returned by `tick`. These are kept
+    /// apart from `notes_between` so they only ever get played live.
+    pub fn clicks_between(&self, from: f32, to: f32) -> Vec<Note> {
+        let (start, end) = self.loop_bounds();
+        if to < from {
+            let mut clicks = self.clicks_between(from, end);
+            clicks.extend(self.clicks_between(start, to));
+            return clicks
+        }
+
+        beats_between(from, to, self.steps_per_beat, self.time_signature.beats, (start as i32, end as i32))
+            // The count-in always clicks, even with the metronome off
+            .filter(|(step, _)| *step < start as i32 || self.prefs.metronome.enabled)
+            .map(|(_, accent)| self.prefs.metronome.click(accent))
+            .collect()
+    }
+
     pub fn play(&mut self, note: Note) {
-        note.play(self.output_stream.mixer())
+        self.synth.play(note)
     }
-}
\ No newline at end of file
+}
+
+/// How late (in ticks) a tick plays with a given amount of swing: the even ones are on
+/// time and the odd ones are pushed back.
+pub fn swing_offset(tick: u32, swing: f32) -> f32 {
+    if tick % 2 == 1 { swing } else { 0.0 }
+}
+
+/// Which bar, beat and step a step of the loop is
+pub fn position(step: u32, steps_per_beat: u32, time_signature: TimeSignature) -> Position {
+    let steps_per_beat = steps_per_beat.max(1);
+    let beat = step / steps_per_beat;
+    Position {
+        bar: beat / time_signature.beats.max(1) + 1,
+        beat: beat % time_signature.beats.max(1) + 1,
+        step: step % steps_per_beat + 1
+    }
+}
+
+/// The step of every beat that falls after `from` and no later than `to`, and whether it's
+/// the first beat of a bar, when going around the steps `start..end`. Steps before the loop
+/// are the count-in, which is a whole number of bars long.
+fn beats_between(from: f32, to: f32, steps_per_beat: u32, beats: u32, (start, end): (i32, i32)) -> impl Iterator<Item=(i32, bool)> {
+    let steps_per_beat = steps_per_beat.max(1) as i32;
+    let beats = beats.max(1) as i32;
+    let first = (from / steps_per_beat as f32).floor() as i32 + 1;
+    let last = (to / steps_per_beat as f32).floor() as i32;
+    (first..=last).map(move |beat| {
+        let step = beat * steps_per_beat;
+        let in_bar = if step < start {
+            (step - start).rem_euclid(steps_per_beat * beats)
+        } else {
+            // The end of the loop is its start again
+            let step = start + (step - start).rem_euclid((end - start).max(1));
+            step % LOOP_LENGTH as i32
+        };
+        (step, in_bar / steps_per_beat % beats == 0)
+    })
+}
+
+/// Where a time in the loop (like from `Grid::cell_time`) falls when we're going around
+/// `start..end`: in (start ..= end), so the start of the region is at its very end, the same
+/// way the first step is at the end of the whole loop.
+fn loop_time(t: f32, start: f32, end: f32) -> f32 {
+    let t = (t - start).rem_euclid(end - start);
+    if t <= 0.0 { end } else { start + t }
+}
+
+/// Whether a time in the loop is in a stretch of it, as returned by `tick`
+fn crossed((from, to): (f32, f32), t: f32) -> bool {
+    if to < from { t > from || t <= to } else { t > from && t <= to }
+}
+
+/// Every tick (counting `per_step` ticks to a step) that falls after `from` and no later than `to`
+fn ticks_between(from: f32, to: f32, per_step: f32) -> impl Iterator<Item=u32> {
+    let first = (from * per_step).floor() as u32 + 1;
+    let last = (to * per_step).floor() as u32;
+    first..=last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_between() {
+        assert_eq!(ticks_between(0.0, 0.0, 1.0).collect::<Vec<_>>(), vec![]);
+        assert_eq!(ticks_between(0.9, 1.0, 1.0).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(ticks_between(1.0, 1.9, 1.0).collect::<Vec<_>>(), vec![]);
+        assert_eq!(ticks_between(15.9, 16.0, 1.0).collect::<Vec<_>>(), vec![16]);
+        assert_eq!(ticks_between(1.1, 1.6, 4.0).collect::<Vec<_>>(), vec![5, 6]);
+        assert_eq!(ticks_between(-0.5, 0.5, 1.0).collect::<Vec<_>>(), vec![]);
+    }
+
+    #[test]
+    fn test_beats_between() {
+        // Two bars of 3/4 counting in, then into the loop, where the end is also the start
+        let beats: Vec<_> = beats_between(-24.5, 4.0, 4, 3, (0, 16)).collect();
+        assert_eq!(beats, vec![(-24, true), (-20, false), (-16, false), (-12, true), (-8, false), (-4, false), (0, true), (4, false)]);
+        let beats: Vec<_> = beats_between(11.0, 16.0, 4, 3, (0, 16)).collect();
+        assert_eq!(beats, vec![(12, true), (16, true)]);
+
+        // Looping steps 4..12 in 4/4, the end of the region is beat 2 again
+        let beats: Vec<_> = beats_between(7.0, 12.0, 4, 4, (4, 12)).collect();
+        assert_eq!(beats, vec![(8, false), (12, false)]);
+    }
+
+    #[test]
+    fn test_loop_time() {
+        assert_eq!(loop_time(16.0, 0.0, 16.0), 16.0);
+        assert_eq!(loop_time(3.0, 0.0, 16.0), 3.0);
+        // The start of a region is at its end, even when nudged a little early
+        assert_eq!(loop_time(4.0, 4.0, 12.0), 12.0);
+        assert_eq!(loop_time(3.75, 4.0, 12.0), 11.75);
+        assert_eq!(loop_time(16.0, 0.0, 8.0), 8.0);
+        assert_eq!(loop_time(5.5, 4.0, 12.0), 5.5);
+    }
+
+    /// A recorded note is heard when its key is pressed, and not again when the playhead
+    /// gets to its cell on the same time round
+    /// An arpeggio that doesn't fit the loop evenly keeps going round it
+    #[test]
+    fn test_arp_across_loops() {
+        let (mut tenori, _synth) = Tenori::headless();
+        let mut grid = Grid::new(Id::new("test"));
+        for column in 0..LOOP_LENGTH {
+            for row in [0, 2, 4] {
+                grid.toggle((row * LOOP_LENGTH + column) as usize)
+            }
+        }
+        grid.arp.enabled = true;
+        tenori.grids.push(grid);
+        tenori.playing = true;
+        tenori.timer = -0.001;
+
+        let step = 1.0 / (tenori.tempo * tenori.steps_per_beat as f32 / 60.0);
+        let mut tones = vec![];
+        while tenori.loops < 3 {
+            let (from, to) = tenori.advance(step * 0.3);
+            tones.extend(tenori.notes_between(from, to).iter().map(|n| n.tone));
+        }
+        // 16 ticks a loop, and one more for the top of the next
+        assert_eq!(tones.len(), 3 * LOOP_LENGTH as usize + 1);
+        let mut chord = tones[..3].to_vec();
+        chord.sort();
+        chord.dedup();
+        assert_eq!(chord.len(), 3);
+        for (n, tone) in tones.iter().enumerate() {
+            assert_eq!(*tone, tones[n % 3], "tick {n}")
+        }
+    }
+
+    #[test]
+    fn test_record_once() {
+        for quantize in [1.0, 0.0] {
+            let (mut tenori, _synth) = Tenori::headless();
+            tenori.grids.push(Grid::new(Id::new("test")));
+            tenori.prefs.recorder.quantize = quantize;
+            tenori.playing = true;
+            let step = 1.0 / (tenori.tempo * tenori.steps_per_beat as f32 / 60.0);
+            tenori.timer = 3.5;
+            tenori.advance(step * 0.1);
+            tenori.record(0);
+
+            let mut heard = 0;
+            for _ in 0..16 {
+                let (from, to) = tenori.advance(step * 0.1);
+                heard += tenori.notes_between(from, to).len();
+            }
+            assert!(tenori.timer > 4.0);
+            assert_eq!(heard, 0, "quantize {quantize}");
+
+            // It's there next time round
+            for _ in 0..160 {
+                let (from, to) = tenori.advance(step * 0.1);
+                heard += tenori.notes_between(from, to).len();
+            }
+            assert_eq!(heard, 1, "quantize {quantize}");
+        }
+    }
+
+    /// Replace only starts clearing once something's been recorded
+    #[test]
+    fn test_punch_in() {
+        let (mut tenori, _synth) = Tenori::headless();
+        let mut grid = Grid::new(Id::new("test"));
+        for column in 0..LOOP_LENGTH {
+            grid.toggle((15 * LOOP_LENGTH + column) as usize)
+        }
+        tenori.grids.push(grid);
+        tenori.prefs.recorder.enabled = true;
+        tenori.prefs.recorder.mode = RecordMode::Replace;
+        tenori.playing = true;
+
+        tenori.clear_for_recording(1.0, 6.0);
+        assert_eq!(tenori.grids[0].notes.iter().filter(|n| **n).count(), 16);
+
+        tenori.timer = 6.2;
+        tenori.record(-9);
+        tenori.clear_for_recording(6.2, 8.0);
+        let bottom: Vec<bool> = (0..LOOP_LENGTH).map(|c| tenori.grids[0].notes[(15 * LOOP_LENGTH + c) as usize]).collect();
+        // The note went in on the bottom row, and the next two columns were cleared
+        let cleared: Vec<u32> = (0..LOOP_LENGTH).filter(|c| !bottom[*c as usize]).collect();
+        assert_eq!(cleared, vec![7, 8]);
+        assert_eq!(tenori.grids[0].notes.iter().filter(|n| **n).count(), 14);
+    }
+
+    #[test]
+    fn test_new_song() {
+        let (mut tenori, _synth) = Tenori::headless();
+        let fresh = tenori.to_text().unwrap();
+        tenori.grids.push(Grid::new(Id::new("test")));
+        tenori.tempo = 120.0;
+        tenori.playing = true;
+        tenori.scenes.queued = Some(0);
+        tenori.tap();
+
+        tenori.new_song();
+        assert_eq!(tenori.to_text().unwrap(), fresh);
+        assert!(!tenori.playing && tenori.scenes.queued.is_none() && tenori.taps.is_empty());
+    }
+
+    #[test]
+    fn test_position() {
+        let four_four = TimeSignature { beats: 4, unit: 4 };
+        assert_eq!(position(0, 4, four_four), Position { bar: 1, beat: 1, step: 1 });
+        assert_eq!(position(6, 4, four_four), Position { bar: 1, beat: 2, step: 3 });
+        assert_eq!(position(15, 4, four_four), Position { bar: 1, beat: 4, step: 4 });
+        assert_eq!(position(5, 1, four_four), Position { bar: 2, beat: 2, step: 1 });
+
+        let three_four = TimeSignature { beats: 3, unit: 4 };
+        assert_eq!(position(12, 2, three_four), Position { bar: 3, beat: 1, step: 1 });
+    }
+}
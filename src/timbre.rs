@@ -8,13 +8,27 @@ use crate::envelope::Envelope;
 use crate::gui::Showable;
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
 pub struct Timbre {
-    sine: f32,
-    triangle: f32,
-    square: f32,
-    sawtooth: f32,
-    noise: f32,
-    envelope: Envelope
+    pub sine: f32,
+    pub triangle: f32,
+    pub square: f32,
+    pub sawtooth: f32,
+    pub noise: f32,
+    pub envelope: Envelope,
+
+    /// How long (in seconds) a mono or legato track takes to slide from one note to the next
+    pub glide: f32,
+    pub glide_mode: GlideMode
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum GlideMode {
+    /// Slide from whatever note the track played last
+    #[default]
+    Always,
+    /// Only slide when the last note is still sounding
+    Tied
 }
 
 impl Default for Timbre {
@@ -26,12 +40,15 @@ impl Default for Timbre {
             sawtooth: 0.0,
             noise: 0.0,
             envelope: Default::default(),
+            glide: 0.0,
+            glide_mode: GlideMode::Always
         }
     }
 }
 
 impl Timbre {
-    pub fn source(self, frequency: f32) -> impl Source {
+    /// A source playing this timbre at a frequency, starting `elapsed` samples into the envelope
+    pub fn source(self, frequency: f32, elapsed: usize) -> impl Source {
         let (mixer, source) = rodio::mixer::mixer(1, 44100);
         if self.sine > 0.0 {
             mixer.add(SineWave::new(frequency).amplify(self.sine))
@@ -48,7 +65,7 @@ impl Timbre {
         if self.noise > 0.0 {
             mixer.add(WhiteUniform::new(44100).amplify(self.noise))
         }
-        self.envelope.modulate(source)
+        self.envelope.modulate_from(source, elapsed)
     }
 }
 
@@ -97,6 +114,13 @@ impl Showable<(Id, String)> for (&mut Timbre, &mut bool, &mut String) {
                 ui.add(Label::new("Release"));
                 ui.add(Slider::new(&mut self.0.envelope.release, RangeInclusive::new(0.0, 1.0)));
                 ui.end_row();
+
+                ui.add(Label::new("Glide"))
+                    .on_hover_text("Only used when the track's voices are mono or legato");
+                ui.add(Slider::new(&mut self.0.glide, RangeInclusive::new(0.0, 1.0)));
+                ui.radio_value(&mut self.0.glide_mode, GlideMode::Always, "Always");
+                ui.radio_value(&mut self.0.glide_mode, GlideMode::Tied, "Tied only");
+                ui.end_row();
             });
         });
 
@@ -0,0 +1,107 @@
+use rand::Rng;
+use crate::clip::Clip;
+
+/// Ways of rearranging a block of cells
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Move everything a step earlier or later, wrapping around
+    RotateLeft,
+    RotateRight,
+    /// Move everything up or down a row (a scale degree), losing anything pushed off the edge
+    Up,
+    Down,
+    /// Play backwards
+    Reverse,
+    /// Turn upside down
+    Flip,
+    /// Turn every cell on or off
+    Invert,
+    /// Play twice as fast, so it goes round twice
+    Double,
+    /// Play half as fast, so only the first half fits
+    Halve,
+    /// Turn off this fraction (0.0 .. 1.0) of the lit cells, at random
+    Thin(f32)
+}
+
+impl Clip {
+    /// This clip, rearranged
+    pub fn transform(&self, transform: Transform) -> Clip {
+        let (w, h) = (self.width, self.height);
+        if w == 0 || h == 0 { return self.clone() }
+
+        // Where each cell's notes come from, if anywhere, and what happens to its nudge
+        let source = |x: usize, y: usize| -> Option<(usize, i8)> {
+            let at = |x: usize, y: usize| x + y * w;
+            let nudge = |n: usize| self.nudges[n];
+            match transform {
+                Transform::RotateLeft => Some(at((x + 1) % w, y)).map(|n| (n, nudge(n))),
+                Transform::RotateRight => Some(at((x + w - 1) % w, y)).map(|n| (n, nudge(n))),
+                Transform::Up => (y + 1 < h).then(|| at(x, y + 1)).map(|n| (n, nudge(n))),
+                Transform::Down => (y > 0).then(|| at(x, y - 1)).map(|n| (n, nudge(n))),
+                // Backwards, early notes are late
+                Transform::Reverse => Some(at(w - 1 - x, y)).map(|n| (n, -nudge(n))),
+                Transform::Flip => Some(at(x, h - 1 - y)).map(|n| (n, nudge(n))),
+                Transform::Double => Some(at(x * 2 % w, y)).map(|n| (n, nudge(n) / 2)),
+                Transform::Halve => x.is_multiple_of(2).then(|| at(x / 2, y)).map(|n| (n, nudge(n).saturating_mul(2).clamp(-50, 50))),
+                Transform::Invert | Transform::Thin(_) => Some((at(x, y), nudge(at(x, y))))
+            }
+        };
+
+        let mut rng = rand::rng();
+        let mut notes = Vec::with_capacity(w * h);
+        let mut nudges = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                let (lit, nudge) = match source(x, y) {
+                    Some((n, nudge)) => (self.notes[n], nudge),
+                    None => (false, 0)
+                };
+                let lit = match transform {
+                    Transform::Invert => !lit,
+                    Transform::Thin(fraction) => lit && !rng.random_bool(fraction.clamp(0.0, 1.0) as f64),
+                    _ => lit
+                };
+                notes.push(lit);
+                nudges.push(if lit { nudge } else { 0 });
+            }
+        }
+
+        Clip { width: w, height: h, notes, nudges }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clip from rows of 0s and 1s
+    fn clip(rows: &[&str]) -> Clip {
+        let notes: Vec<bool> = rows.concat().chars().map(|c| c == '1').collect();
+        Clip { width: rows[0].len(), height: rows.len(), nudges: vec![0; notes.len()], notes }
+    }
+
+    #[test]
+    fn test_transforms() {
+        let c = clip(&["1100", "0001"]);
+        assert_eq!(c.transform(Transform::RotateLeft), clip(&["1001", "0010"]));
+        assert_eq!(c.transform(Transform::RotateRight), clip(&["0110", "1000"]));
+        assert_eq!(c.transform(Transform::Up), clip(&["0001", "0000"]));
+        assert_eq!(c.transform(Transform::Down), clip(&["0000", "1100"]));
+        assert_eq!(c.transform(Transform::Reverse), clip(&["0011", "1000"]));
+        assert_eq!(c.transform(Transform::Flip), clip(&["0001", "1100"]));
+        assert_eq!(c.transform(Transform::Invert), clip(&["0011", "1110"]));
+        assert_eq!(c.transform(Transform::Double), clip(&["1010", "0000"]));
+        assert_eq!(c.transform(Transform::Halve), clip(&["1010", "0000"]));
+        assert_eq!(c.transform(Transform::Thin(0.0)), c);
+        assert_eq!(c.transform(Transform::Thin(1.0)), clip(&["0000", "0000"]));
+    }
+
+    #[test]
+    fn test_nudges() {
+        let mut c = clip(&["10"]);
+        c.nudges[0] = 20;
+        assert_eq!(c.transform(Transform::Reverse).nudges, vec![0, -20]);
+        assert_eq!(c.transform(Transform::Invert).nudges, vec![0, 0]);
+    }
+}
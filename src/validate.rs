@@ -0,0 +1,137 @@
+use std::fmt::{self, Display};
+use std::ops::{Range, RangeInclusive};
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+/// Something wrong with a song file: what's wrong, and where it is as near as we can tell
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadError {
+    /// Counting from 1
+    pub line: Option<usize>,
+
+    /// Where in the song it is, like `grids[2].notes`
+    pub field: Option<String>,
+    pub reason: String
+}
+
+impl LoadError {
+    /// A problem with the file as a whole, like not being able to open it
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self { line: None, field: None, reason: reason.into() }
+    }
+
+    /// The file isn't TOML, or isn't shaped like a song. The parser knows the line, but
+    /// not the field.
+    pub fn parse(text: &str, error: toml::de::Error) -> Self {
+        Self {
+            line: error.span().map(|span| line_of(text, span.start)),
+            field: None,
+            reason: error.message().trim_end().to_string()
+        }
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, &self.field) {
+            (Some(line), Some(field)) => write!(f, "line {line}, {field}: {}", self.reason),
+            (Some(line), None) => write!(f, "line {line}: {}", self.reason),
+            (None, Some(field)) => write!(f, "{field}: {}", self.reason),
+            (None, None) => write!(f, "{}", self.reason)
+        }
+    }
+}
+
+/// Which line a byte offset is on, counting from 1
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// The field a stretch of a document is in, like `grids[2].notes`. None if it's the whole
+/// of `value`.
+fn field_at(value: &DeValue, span: &Range<usize>) -> Option<String> {
+    let fields: Vec<(String, &Spanned<DeValue>)> = match value {
+        DeValue::Table(table) => table.iter().map(|(key, value)| (key.get_ref().to_string(), value)).collect(),
+        DeValue::Array(array) => array.iter().enumerate().map(|(n, value)| (format!("[{n}]"), value)).collect(),
+        _ => return None
+    };
+    fields.into_iter().find_map(|(name, value)| {
+        // Tables only span their headers, so what's in them has to be looked at as well
+        let inside = value.span().start <= span.start && span.end <= value.span().end;
+        match field_at(value.get_ref(), span) {
+            Some(rest) if rest.starts_with('[') => Some(format!("{name}{rest}")),
+            Some(rest) => Some(format!("{name}.{rest}")),
+            None => inside.then_some(name)
+        }
+    })
+}
+
+/// Goes through a song that's been read, putting right what it can and keeping a list of
+/// what it put right
+pub struct Repairs<'a> {
+    text: &'a str,
+
+    /// The file again, with where everything is in it
+    document: Option<DeValue<'a>>,
+    pub repaired: Vec<LoadError>
+}
+
+impl<'a> Repairs<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let document = DeTable::parse(text).ok().map(|table| DeValue::Table(table.into_inner()));
+        Self { text, document, repaired: vec![] }
+    }
+
+    /// Which line a field is on. If it's not in the file (because it was left out and
+    /// took its default), it's the line of the nearest thing around it that is.
+    fn line(&self, field: &str) -> Option<usize> {
+        let mut value = self.document.as_ref()?;
+        let mut span = None;
+        'path: for part in field.split('.') {
+            let mut indices = part.split('[');
+            let Some(found) = indices.next().and_then(|name| value.get(name)) else { break };
+            span = Some(found.span());
+            value = found.get_ref();
+            for index in indices {
+                let Some(found) = index.trim_end_matches(']').parse::<usize>().ok().and_then(|i| value.get(i)) else { break 'path };
+                span = Some(found.span());
+                value = found.get_ref();
+            }
+        }
+        span.map(|span| line_of(self.text, span.start))
+    }
+
+    /// The song wouldn't read from `text`, which is the file, or what it became when it was
+    /// brought up to date from an older version. Either way the line is the one in the file.
+    pub fn unreadable(&self, text: &str, error: toml::de::Error) -> LoadError {
+        let field = error.span().and_then(|span| {
+            let table = DeTable::parse(text).ok()?;
+            field_at(&DeValue::Table(table.into_inner()), &span)
+        });
+        let line = match &field {
+            Some(field) => self.line(field),
+            None if text == self.text => error.span().map(|span| line_of(text, span.start)),
+            None => None
+        };
+        LoadError { line, field, reason: error.message().trim_end().to_string() }
+    }
+
+    /// Something wrong with a field, that can't be put right
+    pub fn error(&self, field: &str, reason: impl Into<String>) -> LoadError {
+        LoadError { line: self.line(field), field: Some(field.to_string()), reason: reason.into() }
+    }
+
+    /// Note that something's been put right
+    pub fn note(&mut self, field: &str, reason: impl Into<String>) {
+        let error = self.error(field, reason);
+        self.repaired.push(error)
+    }
+
+    /// Keep a number in range. Anything that's not a number (NaN) goes to the bottom of it.
+    pub fn clamp<T: PartialOrd + Copy + Display>(&mut self, field: &str, value: &mut T, range: RangeInclusive<T>) {
+        if range.contains(value) { return }
+        let fixed = if *value > *range.end() { *range.end() } else { *range.start() };
+        self.note(field, format!("{value} should be from {} to {}, so it's now {fixed}", range.start(), range.end()));
+        *value = fixed
+    }
+}
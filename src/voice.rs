@@ -0,0 +1,420 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+use eframe::egui::Id;
+use rodio::{ChannelCount, SampleRate, Source};
+use serde::{Deserialize, Serialize};
+
+/// How many voices can sound at once across all tracks, unless the user changes it
+pub const DEFAULT_MAX_VOICES: usize = 32;
+
+/// How many samples a stolen voice takes to fade out (about 5ms at 44.1kHz)
+pub(crate) const STEAL_FADE: usize = 220;
+
+/// A voice that has fallen below this fraction of its loudest level is considered to be in
+/// its tail, and is stolen first
+const QUIET: f32 = 0.01;
+
+/// How quickly the level meter on a voice falls, per sample
+const PEAK_DECAY: f32 = 0.999;
+
+/// Follows how loud a voice is, so we can tell when it's in its tail
+#[derive(Copy, Clone, Default)]
+pub(crate) struct LevelMeter {
+    peak: f32,
+    loudest: f32
+}
+
+impl LevelMeter {
+    pub(crate) fn update(&mut self, val: f32) {
+        self.peak = val.abs().max(self.peak * PEAK_DECAY);
+        self.loudest = self.loudest.max(self.peak);
+    }
+
+    /// The recent peak, as a fraction of the loudest it's been
+    pub(crate) fn level(&self) -> f32 {
+        if self.loudest > 0.0 { self.peak / self.loudest } else { 0.0 }
+    }
+}
+
+/// Pick a voice to make fade out, given each one's level and serial. Voices that are
+/// nearly silent go first, then the oldest. The synth's voice pool steals the same way.
+pub(crate) fn victim<T>(voices: impl Iterator<Item=T>, key: impl Fn(&T) -> (f32, u64)) -> Option<T> {
+    voices.min_by_key(|v| {
+        let (level, serial) = key(v);
+        (level >= QUIET, serial)
+    })
+}
+
+/// How often (in samples) a voice reports its level and position back to the allocator
+const REPORT_EVERY: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub enum VoiceMode {
+    /// Every note gets its own voice, up to the track's polyphony
+    #[default]
+    Poly,
+    /// A new note cuts off whatever the track was playing
+    Mono,
+    /// Like mono, but a note that arrives while the last one is still sounding
+    /// continues its envelope instead of starting a new attack
+    Legato
+}
+
+impl VoiceMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            VoiceMode::Poly => "Poly",
+            VoiceMode::Mono => "Mono",
+            VoiceMode::Legato => "Legato"
+        }
+    }
+}
+
+/// The voice settings for a single track
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Voices {
+    pub mode: VoiceMode,
+
+    /// How many voices this track can have sounding at once, in poly mode
+    pub polyphony: usize
+}
+
+impl Default for Voices {
+    fn default() -> Self {
+        Self {
+            mode: VoiceMode::Poly,
+            polyphony: 8
+        }
+    }
+}
+
+/// The parts of a playing voice that both the audio thread and the allocator can see
+struct VoiceState {
+    /// Set by the allocator to make the voice fade out and stop
+    stolen: AtomicBool,
+
+    /// Set by the voice when it's done playing (or has been dropped)
+    finished: AtomicBool,
+
+    /// The recent peak amplitude of the voice relative to its loudest, as f32 bits
+    level: AtomicU32,
+
+    /// How many samples into its envelope the voice is
+    elapsed: AtomicUsize
+}
+
+impl Default for VoiceState {
+    fn default() -> Self {
+        Self {
+            stolen: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            // Until it tells us otherwise, assume a new voice is at full volume
+            level: AtomicU32::new(1.0f32.to_bits()),
+            elapsed: AtomicUsize::new(0)
+        }
+    }
+}
+
+impl VoiceState {
+    fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    fn sounding(&self) -> bool {
+        !self.stolen.load(Ordering::Relaxed) && !self.finished.load(Ordering::Relaxed)
+    }
+}
+
+struct ActiveVoice {
+    track: Id,
+    serial: u64,
+    state: Arc<VoiceState>
+}
+
+/// A voice reserved by the allocator, which hasn't started playing yet. If it's dropped
+/// without being wrapped, it never plays, so it gives its place back.
+pub struct Voice {
+    state: Arc<VoiceState>,
+    offset: usize
+}
+
+impl Voice {
+    /// How many samples into its envelope this voice should start (nonzero for legato notes)
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Start no further in than `max` samples, the way `Envelope::modulate_from` does, so
+    /// what we report back matches where the envelope really is
+    pub fn clamp_offset(&mut self, max: usize) {
+        self.offset = self.offset.min(max)
+    }
+
+    /// Wrap a source so that the allocator can track and steal it
+    pub fn wrap<S: Source>(mut self, source: S) -> VoiceSource<S> {
+        VoiceSource {
+            source,
+            // The source looks after the state from now on. We're left with one nobody
+            // else has, for `drop` to finish.
+            state: std::mem::take(&mut self.state),
+            elapsed: self.offset,
+            fade: STEAL_FADE,
+            meter: LevelMeter::default()
+        }
+    }
+}
+
+impl Drop for Voice {
+    fn drop(&mut self) {
+        self.state.finished.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Keeps track of every voice we've started, and decides which ones to cut off when
+/// a track (or the whole program) has too many going at once. This is for notes played as
+/// rodio chains (`Note::play`); the `Synth` has its own pool of voices, stolen the same way.
+pub struct VoiceAllocator {
+    /// The most voices that can sound at once, across all tracks
+    pub max_voices: usize,
+
+    voices: Vec<ActiveVoice>,
+
+    // Increases with every voice so we can tell which is oldest
+    serial: u64
+}
+
+impl Default for VoiceAllocator {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_VOICES)
+    }
+}
+
+impl VoiceAllocator {
+    pub fn new(max_voices: usize) -> Self {
+        Self {
+            max_voices,
+            voices: vec![],
+            serial: 0
+        }
+    }
+
+    /// How many voices are sounding (not counting ones that are fading out after being stolen)
+    pub fn sounding(&self, track: Option<Id>) -> usize {
+        self.voices.iter()
+            .filter(|v| track.is_none_or(|t| t == v.track) && v.state.sounding())
+            .count()
+    }
+
+    /// Reserve a voice for a new note on a track, stealing older voices to stay under both
+    /// the track's limit and the global one.
+    pub fn allocate(&mut self, track: Id, voices: Voices) -> Voice {
+        self.voices.retain(|v| !v.state.finished.load(Ordering::Relaxed));
+
+        let mut offset = 0;
+        match voices.mode {
+            VoiceMode::Poly => {
+                while self.sounding(Some(track)) >= voices.polyphony.max(1) {
+                    self.steal(Some(track))
+                }
+            }
+            VoiceMode::Mono | VoiceMode::Legato => {
+                for v in self.voices.iter().filter(|v| v.track == track && v.state.sounding()) {
+                    if voices.mode == VoiceMode::Legato {
+                        offset = offset.max(v.state.elapsed.load(Ordering::Relaxed))
+                    }
+                    v.state.stolen.store(true, Ordering::Relaxed)
+                }
+            }
+        }
+
+        while self.sounding(None) >= self.max_voices.max(1) {
+            self.steal(None)
+        }
+
+        let state = Arc::new(VoiceState::default());
+        state.elapsed.store(offset, Ordering::Relaxed);
+        self.serial += 1;
+        self.voices.push(ActiveVoice {
+            track,
+            serial: self.serial,
+            state: state.clone()
+        });
+
+        Voice { state, offset }
+    }
+
+    /// Pick a voice (from one track, or all of them) and make it fade out
+    fn steal(&mut self, track: Option<Id>) {
+        let candidates = self.voices.iter()
+            .filter(|v| track.is_none_or(|t| t == v.track) && v.state.sounding());
+
+        if let Some(v) = victim(candidates, |v| (v.state.level(), v.serial)) {
+            v.state.stolen.store(true, Ordering::Relaxed)
+        }
+    }
+}
+
+/// A source being played by a voice. It reports its level back to the allocator, and fades
+/// out quickly if the voice gets stolen.
+pub struct VoiceSource<S: Source> {
+    source: S,
+    state: Arc<VoiceState>,
+    elapsed: usize,
+    fade: usize,
+    meter: LevelMeter
+}
+
+impl<S: Source> Iterator for VoiceSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut val = self.source.next()?;
+
+        if self.state.stolen.load(Ordering::Relaxed) {
+            if self.fade == 0 { return None }
+            val *= self.fade as f32 / STEAL_FADE as f32;
+            self.fade -= 1;
+        }
+
+        self.meter.update(val);
+        self.elapsed += 1;
+        if self.elapsed.is_multiple_of(REPORT_EVERY) {
+            self.state.level.store(self.meter.level().to_bits(), Ordering::Relaxed);
+            self.state.elapsed.store(self.elapsed, Ordering::Relaxed);
+        }
+
+        Some(val)
+    }
+}
+
+impl<S: Source> Drop for VoiceSource<S> {
+    fn drop(&mut self) {
+        // The mixer drops sources once they're done, so this is how the allocator finds out
+        self.state.finished.store(true, Ordering::Relaxed)
+    }
+}
+
+impl<S: Source> Source for VoiceSource<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poly(polyphony: usize) -> Voices {
+        Voices { mode: VoiceMode::Poly, polyphony }
+    }
+
+    fn mode(mode: VoiceMode) -> Voices {
+        Voices { mode, polyphony: 8 }
+    }
+
+    #[test]
+    fn test_track_limit() {
+        let mut alloc = VoiceAllocator::new(100);
+        let (a, b) = (Id::new("a"), Id::new("b"));
+        let _voices: Vec<_> = (0..10).map(|_| alloc.allocate(a, poly(4))).collect();
+        assert_eq!(alloc.sounding(Some(a)), 4);
+
+        // Another track has its own limit
+        let _more: Vec<_> = (0..3).map(|_| alloc.allocate(b, poly(4))).collect();
+        assert_eq!(alloc.sounding(Some(b)), 3);
+        assert_eq!(alloc.sounding(None), 7);
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let mut alloc = VoiceAllocator::new(6);
+        let mut voices = vec![];
+        for n in 0..20 {
+            let track = Id::new(n % 3);
+            voices.push(alloc.allocate(track, poly(16)));
+            assert!(alloc.sounding(None) <= 6);
+        }
+        assert_eq!(alloc.sounding(None), 6);
+
+        // Voices that are dropped without ever playing don't hold on to their places
+        voices.clear();
+        assert_eq!(alloc.sounding(None), 0);
+    }
+
+    #[test]
+    fn test_steals_quiet_then_oldest() {
+        let mut alloc = VoiceAllocator::new(3);
+        let track = Id::new("a");
+        let first = alloc.allocate(track, poly(8));
+        let second = alloc.allocate(track, poly(8));
+        let third = alloc.allocate(track, poly(8));
+        first.state.level.store(0.5f32.to_bits(), Ordering::Relaxed);
+        second.state.level.store(0.001f32.to_bits(), Ordering::Relaxed);
+        third.state.level.store(0.5f32.to_bits(), Ordering::Relaxed);
+
+        // The second voice is in its tail, so it goes before the older first one
+        let _fourth = alloc.allocate(track, poly(8));
+        assert!(first.state.sounding());
+        assert!(!second.state.sounding());
+
+        // Now they're all loud, so the oldest goes
+        let _fifth = alloc.allocate(track, poly(8));
+        assert!(!first.state.sounding());
+        assert!(third.state.sounding());
+    }
+
+    #[test]
+    fn test_mono_and_legato() {
+        let mut alloc = VoiceAllocator::new(32);
+        let track = Id::new("a");
+        let first = alloc.allocate(track, mode(VoiceMode::Mono));
+        first.state.elapsed.store(1000, Ordering::Relaxed);
+        let second = alloc.allocate(track, mode(VoiceMode::Mono));
+        assert_eq!(alloc.sounding(Some(track)), 1);
+        assert_eq!(second.offset(), 0);
+
+        second.state.elapsed.store(500, Ordering::Relaxed);
+        let third = alloc.allocate(track, mode(VoiceMode::Legato));
+        assert_eq!(alloc.sounding(Some(track)), 1);
+        assert_eq!(third.offset(), 500);
+
+        // Picking up a voice that's gone past its decay starts at the hold instead
+        third.state.elapsed.store(1_000_000, Ordering::Relaxed);
+        let mut fourth = alloc.allocate(track, mode(VoiceMode::Legato));
+        fourth.clamp_offset(300);
+        assert_eq!(fourth.offset(), 300);
+        let mut src = fourth.wrap(rodio::source::SineWave::new(440.0));
+        src.by_ref().take(REPORT_EVERY).count();
+        let reported = src.state.elapsed.load(Ordering::Relaxed);
+        assert!(reported > 300 && reported <= 300 + REPORT_EVERY, "{reported}");
+    }
+
+    #[test]
+    fn test_stolen_voice_fades() {
+        let mut alloc = VoiceAllocator::new(1);
+        let track = Id::new("a");
+        let voice = alloc.allocate(track, poly(8));
+        let state = voice.state.clone();
+        let mut src = voice.wrap(rodio::source::SineWave::new(440.0));
+        assert!(src.next().is_some());
+
+        let _next = alloc.allocate(track, poly(8));
+        assert_eq!(src.by_ref().count(), STEAL_FADE);
+        drop(src);
+        assert!(state.finished.load(Ordering::Relaxed));
+    }
+}